@@ -82,6 +82,16 @@ pub struct PatchingConfig {
     pub check_integrity: bool,
     #[serde(default)]
     pub create_grf: bool,
+    #[serde(default = "default_grf_version")]
+    pub new_grf_version: u32,
+    #[serde(default)]
+    pub register_new_grf: bool,
+    /// Registers a created GRF ahead of the existing ones so its files take precedence
+    #[serde(default = "default_true")]
+    pub register_new_grf_first: bool,
+    /// Patches downloaded ahead while earlier ones are applied
+    #[serde(default = "default_concurrent_downloads")]
+    pub concurrent_downloads: u32,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -111,6 +121,7 @@ pub struct UiButtons {
     pub toggle_gray: Option<String>,
     pub toggle_normal: Option<String>,
     pub reset_cache: Option<String>,
+    pub optimize: Option<String>,
     pub cancel: Option<String>,
     pub play: Option<String>,
     pub patching: Option<String>,
@@ -124,6 +135,7 @@ pub struct UiStatus {
     pub checking: Option<String>,
     pub downloading: Option<String>,
     pub patching: Option<String>,
+    pub optimizing: Option<String>,
     pub ready: Option<String>,
     pub error: Option<String>,
 }
@@ -175,6 +187,14 @@ fn default_grf_name() -> String {
     "data.grf".to_string()
 }
 
+fn default_grf_version() -> u32 {
+    0x200
+}
+
+fn default_concurrent_downloads() -> u32 {
+    2
+}
+
 impl Default for WindowConfig {
     fn default() -> Self {
         Self {
@@ -213,6 +233,10 @@ impl Default for PatcherConfig {
                 in_place: true,
                 check_integrity: true,
                 create_grf: false,
+                new_grf_version: default_grf_version(),
+                register_new_grf: false,
+                register_new_grf_first: true,
+                concurrent_downloads: default_concurrent_downloads(),
             },
             messages: None,
             custom_actions: None,
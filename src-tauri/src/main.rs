@@ -4,18 +4,52 @@
 mod config;
 mod patcher;
 
-use config::PatcherConfig;
+use config::{PatchServer, PatcherConfig};
 #[cfg(debug_assertions)]
 use config::load_config;
-use patcher::downloader::{download_patch, DownloadProgress};
-use patcher::downloader::{download_patch, DownloadProgress};
+use patcher::downloader::{DownloadProgress, ExpectedFile};
+use patcher::mirrors::{rank_mirrors, MirrorRanking, MirrorSet};
 use patcher::patch_list::{fetch_patch_list, get_local_cache, save_local_cache, filter_unapplied_patches};
-use patcher::thor_patcher::extract_thor_patch;
+use patcher::thor_patcher::{extract_thor_patch, remove_replaced_executable, PatchOptions, PatchOutcome};
+use patcher::grf::GrfWriter;
+use patcher::grf::journal::recover_all;
+use patcher::grf::types::RepackProgress;
 use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
 use std::process::Command;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 use tauri::{Manager, State};
 
+/// Aborts background tasks when dropped, e.g. downloads ahead of a failed patch
+struct AbortOnDrop(Vec<tokio::task::AbortHandle>);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        for task in &self.0 {
+            task.abort();
+        }
+    }
+}
+
+/// Held by an update or a GRF optimization while it runs: both rewrite the GRFs,
+/// so only one may run at a time. Released when dropped.
+struct BusyGuard(Arc<AtomicBool>);
+
+impl BusyGuard {
+    fn acquire(busy: &Arc<AtomicBool>) -> Result<Self, String> {
+        busy.compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire)
+            .map_err(|_| "An update or GRF optimization is already running".to_string())?;
+        Ok(Self(busy.clone()))
+    }
+}
+
+impl Drop for BusyGuard {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Release);
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct PatchingStatus {
     status: String,
@@ -41,6 +75,10 @@ struct ToggleResult {
 
 struct AppState {
     config: Arc<Mutex<Option<PatcherConfig>>>,
+    /// Result of the last mirror probe, for the UI
+    mirror_ranking: Arc<Mutex<Vec<MirrorRanking>>>,
+    /// Set while an update or a GRF optimization runs, see `BusyGuard`
+    busy: Arc<AtomicBool>,
 }
 
 #[tauri::command]
@@ -49,6 +87,11 @@ fn get_config(state: State<AppState>) -> Result<PatcherConfig, String> {
     config_lock.clone().ok_or_else(|| "No configuration loaded".to_string())
 }
 
+#[tauri::command]
+fn get_mirror_ranking(state: State<AppState>) -> Vec<MirrorRanking> {
+    state.mirror_ranking.lock().unwrap().clone()
+}
+
 #[tauri::command]
 async fn start_update(
     app: tauri::AppHandle,
@@ -59,9 +102,13 @@ async fn start_update(
         config_lock.clone().ok_or_else(|| "No configuration loaded".to_string())?
     };
 
+    let busy = BusyGuard::acquire(&state.busy)?;
     let app_clone = app.clone();
+    let mirror_ranking = state.mirror_ranking.clone();
 
     tokio::spawn(async move {
+        let _busy = busy;
+
         // Send status: checking
         let _ = app_clone.emit_all("patching-status", PatchingStatus {
             status: "checking".to_string(),
@@ -71,12 +118,48 @@ async fn start_update(
             error: None,
         });
 
-        // Fetch patch list with fallback
+        let exe_path = std::env::current_exe().unwrap();
+        let cache_path = exe_path.parent().unwrap().join("autopatcher.dat");
+
+        // Load cache
+        let mut local_cache = get_local_cache(cache_path.to_str().unwrap());
+
+        // Fetch patch list: the configured preferred server first, then the
+        // mirrors in the order of a latency probe
         let mut patches = Vec::new();
         let mut active_server = None;
         let mut last_error = "No patch servers configured".to_string();
 
-        for server in &config.web.patch_servers {
+        let preferred = config.web.preferred_patch_server.as_ref().and_then(|name| {
+            config.web.patch_servers.iter().find(|server| server.name.eq_ignore_ascii_case(name))
+        });
+        let mut candidates: Vec<&PatchServer> = preferred.into_iter().collect();
+        let mut probed = false;
+        let mut next = 0;
+
+        loop {
+            if next == candidates.len() {
+                if probed {
+                    break;
+                }
+                probed = true;
+
+                let ranking = rank_mirrors(&config.web.patch_servers, local_cache.patch_server.as_deref()).await;
+                let _ = app_clone.emit_all("mirror-ranking", &ranking);
+
+                for mirror in &ranking {
+                    let server = config.web.patch_servers.iter().find(|server| server.name == mirror.name);
+                    if let Some(server) = server.filter(|server| !candidates.iter().any(|c| c.name == server.name)) {
+                        candidates.push(server);
+                    }
+                }
+                *mirror_ranking.lock().unwrap() = ranking;
+                continue;
+            }
+
+            let server = candidates[next];
+            next += 1;
+
             match fetch_patch_list(&server.plist_url).await {
                 Ok(p) => {
                     patches = p;
@@ -103,6 +186,12 @@ async fn start_update(
 
         let patch_server = active_server.unwrap();
 
+        // Remembered so the next probe keeps it unless another mirror is clearly faster
+        if local_cache.patch_server.as_deref() != Some(patch_server.name.as_str()) {
+            local_cache.patch_server = Some(patch_server.name.clone());
+            let _ = save_local_cache(cache_path.to_str().unwrap(), &local_cache);
+        }
+
         if patches.is_empty() {
             let _ = app_clone.emit_all("patching-status", PatchingStatus {
                 status: "ready".to_string(),
@@ -115,15 +204,9 @@ async fn start_update(
         }
 
         // Download and apply patches
-        // Download and apply patches
-        let exe_path = std::env::current_exe().unwrap();
         let target_dir = exe_path.parent().unwrap().to_string_lossy().to_string();
-        let cache_path = exe_path.parent().unwrap().join("autopatcher.dat");
         let temp_dir = std::env::temp_dir();
 
-        // Load cache
-        let mut local_cache = get_local_cache(cache_path.to_str().unwrap());
-
         // Filter valid patches (unapplied)
         let patches_to_process = filter_unapplied_patches(&patches, &local_cache);
         
@@ -138,9 +221,58 @@ async fn start_update(
             return;
         }
 
+        // The server that answered the plist first, then the other mirrors
+        let mirrors = Arc::new(MirrorSet::new(
+            std::iter::once(patch_server)
+                .chain(config.web.patch_servers.iter().filter(|s| s.patch_url != patch_server.patch_url))
+                .cloned()
+                .collect(),
+        ));
+
+        // Patches are applied one at a time, in patch list order, while at most
+        // `concurrent_downloads` of the following ones download ahead
+        let look_ahead = config.patching.concurrent_downloads.max(1) as usize;
+        // Only the patch the apply loop is waiting for reports its progress
+        let progress_focus = Arc::new(AtomicUsize::new(0));
+
+        let spawn_download = |i: usize| {
+            let mirrors = mirrors.clone();
+            let progress_focus = progress_focus.clone();
+            let app_for_progress = app_clone.clone();
+            let patch = patches_to_process[i].clone();
+            let temp_path = temp_dir.join(&patch.filename);
+
+            let expected = if config.patching.check_integrity {
+                ExpectedFile {
+                    size: patch.size,
+                    sha256: patch.hash.clone(),
+                }
+            } else {
+                ExpectedFile::default()
+            };
+
+            tokio::spawn(async move {
+                mirrors.download(
+                    &patch,
+                    &temp_path.to_string_lossy(),
+                    &expected,
+                    move |progress: DownloadProgress| {
+                        if progress_focus.load(Ordering::Relaxed) == i {
+                            let _ = app_for_progress.emit_all("download-progress", progress);
+                        }
+                    },
+                ).await
+            })
+        };
+
+        let mut downloads: VecDeque<_> = (0..look_ahead.min(patches_to_process.len())).map(spawn_download).collect();
+
+        // Stops the downloads still running if the update ends early
+        let mut pending_downloads = AbortOnDrop(downloads.iter().map(|download| download.abort_handle()).collect());
+
         for (i, patch) in patches_to_process.iter().enumerate() {
-            let patch_url = format!("{}/{}", patch_server.patch_url, patch.filename);
             let temp_path = temp_dir.join(&patch.filename);
+            progress_focus.store(i, Ordering::Relaxed);
 
             // Send downloading status
             let _ = app_clone.emit_all("patching-status", PatchingStatus {
@@ -151,15 +283,10 @@ async fn start_update(
                 error: None,
             });
 
-            // Download
-            let app_for_progress = app_clone.clone();
-            let download_result = download_patch(
-                &patch_url,
-                &temp_path.to_string_lossy(),
-                move |progress: DownloadProgress| {
-                    let _ = app_for_progress.emit_all("download-progress", progress);
-                },
-            ).await;
+            let download_result = match downloads.pop_front() {
+                Some(download) => download.await.unwrap_or_else(|e| Err(format!("Download task failed: {}", e))),
+                None => Err("Download was never started".to_string()),
+            };
 
             if let Err(e) = download_result {
                 let error_msg = config.messages.as_ref()
@@ -177,6 +304,13 @@ async fn start_update(
                 return;
             }
 
+            // Keep the window full while this one is applied
+            if i + look_ahead < patches_to_process.len() {
+                let download = spawn_download(i + look_ahead);
+                pending_downloads.0.push(download.abort_handle());
+                downloads.push_back(download);
+            }
+
             // Send patching status
             let _ = app_clone.emit_all("patching-status", PatchingStatus {
                 status: "patching".to_string(),
@@ -186,40 +320,34 @@ async fn start_update(
                 error: None,
             });
 
-            // Extract/Apply
-            let target_grf = patch.target_grf.as_deref().unwrap_or(&config.client.default_grf_name);
-            
-            // If forced extract or no target GRF logic (handled inside extract_thor_patch for now, but we pass options here)
-            // Note: Our extract_thor_patch function currently takes (thor_path, target_dir, default_grf_name).
-            // We need to modify it momentarily or rely on its internal logic.
-            // Wait, I should've checked extract_thor_patch signature. It is:
-            // fn extract_thor_patch(thor_path: &str, target_dir: &str, default_grf_name: &str)
-            // It puts data/ files into GRF and others to disk.
-            // If force_extract is true, we might want to bypass that. But for now, let's respect target_grf.
-            
-            // NOTE: Current implementation of extract_thor_patch discriminates based on file path (data/).
-            // To support force_extract correctly, we might need to modify extract_thor_patch later.
-            // For this step, I will use the patch's target_grf if provided.
-            
-            if let Err(e) = extract_thor_patch(
-                &temp_path.to_string_lossy(),
-                &target_dir,
-                target_grf,
-            ) {
-                 let error_msg = config.messages.as_ref()
-                    .and_then(|m| m.patching.as_ref())
-                    .and_then(|p| p.error_extract.clone())
-                    .unwrap_or_else(|| format!("Extraction failed: {}", e));
+            // Extract/Apply, off the async workers so the next downloads keep going
+            let options = PatchOptions::from_patch(patch, &config.client.default_grf_name);
+            let patch_path = temp_path.to_string_lossy().to_string();
+            let apply_dir = target_dir.clone();
+            let patching = config.patching.clone();
 
-                let _ = app_clone.emit_all("patching-status", PatchingStatus {
-                    status: "error".to_string(),
-                    current: None,
-                    total: None,
-                    filename: None,
-                    error: Some(error_msg),
-                });
-                return;
-            }
+            let apply_result = tokio::task::spawn_blocking(move || {
+                extract_thor_patch(&patch_path, &apply_dir, &options, &patching)
+            }).await.unwrap_or_else(|e| Err(format!("Patch task failed: {}", e)));
+
+            let outcome = match apply_result {
+                Ok(outcome) => outcome,
+                Err(e) => {
+                    let error_msg = config.messages.as_ref()
+                        .and_then(|m| m.patching.as_ref())
+                        .and_then(|p| p.error_extract.clone())
+                        .unwrap_or_else(|| format!("Extraction failed: {}", e));
+
+                    let _ = app_clone.emit_all("patching-status", PatchingStatus {
+                        status: "error".to_string(),
+                        current: None,
+                        total: None,
+                        filename: None,
+                        error: Some(error_msg),
+                    });
+                    return;
+                }
+            };
 
             // Cleanup
             let _ = std::fs::remove_file(temp_path);
@@ -230,6 +358,12 @@ async fn start_update(
             local_cache.last_check = chrono::Utc::now().to_rfc3339();
             
             let _ = save_local_cache(cache_path.to_str().unwrap(), &local_cache);
+
+            // The launcher replaced itself: the new one picks up the remaining patches
+            if outcome == PatchOutcome::RestartRequired {
+                app_clone.restart();
+                return;
+            }
         }
 
         // Done
@@ -262,6 +396,88 @@ fn cancel_update(app: tauri::AppHandle) -> Result<(), String> {
     Ok(())
 }
 
+#[tauri::command]
+async fn optimize_grf(
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+) -> Result<CommandResult, String> {
+    let config = {
+        let config_lock = state.config.lock().unwrap();
+        config_lock.clone().ok_or_else(|| "No configuration loaded".to_string())?
+    };
+
+    let exe_path = std::env::current_exe().map_err(|e| e.to_string())?;
+    let exe_dir = exe_path.parent().ok_or("Failed to get exe directory")?.to_path_buf();
+
+    // Every GRF the client may load, without duplicates
+    let mut grf_names = vec![config.client.default_grf_name.clone()];
+    for grfs in [&config.client.normal_grf, &config.client.gray_grf].into_iter().flatten() {
+        for name in grfs.values() {
+            if !grf_names.contains(name) {
+                grf_names.push(name.clone());
+            }
+        }
+    }
+    grf_names.retain(|name| exe_dir.join(name).exists());
+
+    let busy = BusyGuard::acquire(&state.busy)?;
+    let app_clone = app.clone();
+
+    tokio::task::spawn_blocking(move || {
+        let _busy = busy;
+        let writer = GrfWriter::new();
+
+        for (i, grf_name) in grf_names.iter().enumerate() {
+            let _ = app_clone.emit_all("patching-status", PatchingStatus {
+                status: "optimizing".to_string(),
+                current: Some(i as u32 + 1),
+                total: Some(grf_names.len() as u32),
+                filename: Some(grf_name.clone()),
+                error: None,
+            });
+
+            // Only report whole percents, a GRF can hold hundreds of thousands of entries
+            let app_for_progress = app_clone.clone();
+            let mut last_percentage = None;
+            let result = writer.repack(
+                &exe_dir.join(grf_name).to_string_lossy(),
+                move |progress: RepackProgress| {
+                    let percentage = progress.percentage as u32;
+                    if last_percentage != Some(percentage) {
+                        last_percentage = Some(percentage);
+                        let _ = app_for_progress.emit_all("optimize-progress", progress);
+                    }
+                },
+            );
+
+            if let Err(e) = result {
+                let _ = app_clone.emit_all("patching-status", PatchingStatus {
+                    status: "error".to_string(),
+                    current: None,
+                    total: None,
+                    filename: None,
+                    error: Some(format!("Failed to optimize {}: {}", grf_name, e)),
+                });
+                return;
+            }
+        }
+
+        let _ = app_clone.emit_all("patching-status", PatchingStatus {
+            status: "ready".to_string(),
+            current: None,
+            total: None,
+            filename: None,
+            error: None,
+        });
+    });
+
+    Ok(CommandResult {
+        success: true,
+        error: None,
+        message: None,
+    })
+}
+
 #[tauri::command]
 fn launch_game(state: State<AppState>) -> Result<CommandResult, String> {
     let config = {
@@ -538,8 +754,25 @@ fn main() {
         }
     };
 
+    // Leftover from a launcher self-update
+    remove_replaced_executable();
+
+    // Roll back GRF merges interrupted by a crash or a closed window
+    if let Some(exe_dir) = std::env::current_exe().ok().and_then(|p| p.parent().map(|p| p.to_path_buf())) {
+        match recover_all(&exe_dir) {
+            Ok(recovered) => {
+                for grf_name in recovered {
+                    println!("Rolled back interrupted patch of {}", grf_name);
+                }
+            }
+            Err(e) => eprintln!("Failed to recover GRF journals: {}", e),
+        }
+    }
+
     let app_state = AppState {
         config: Arc::new(Mutex::new(config)),
+        mirror_ranking: Arc::new(Mutex::new(Vec::new())),
+        busy: Arc::new(AtomicBool::new(false)),
     };
 
     tauri::Builder::default()
@@ -565,13 +798,15 @@ fn main() {
             get_config,
             start_update,
             cancel_update,
+            optimize_grf,
             launch_game,
             launch_setup,
             sso_login,
             reset_cache,
             open_external,
             toggle_grf,
-            launch_exe
+            launch_exe,
+            get_mirror_ranking
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
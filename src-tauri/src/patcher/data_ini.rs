@@ -0,0 +1,87 @@
+use std::fs;
+use std::path::Path;
+
+/// Adds `grf_name` to the `[Data]` section of the client's data.ini, creating
+/// the section if needed. The client loads GRFs in key order and takes each file
+/// from the first one that has it: with `first` the new GRF becomes `0=` and the
+/// existing entries move down one key, so patched files override older ones;
+/// otherwise it goes after the last entry and only supplies files missing elsewhere.
+pub fn register_grf(target_dir: &str, grf_name: &str, first: bool) -> Result<(), String> {
+    let data_ini_path = Path::new(target_dir).join("data.ini");
+
+    // Read as bytes to handle potential non-UTF8 characters (common in legacy INI files)
+    let content_bytes = if data_ini_path.exists() {
+        fs::read(&data_ini_path).map_err(|e| format!("Failed to read data.ini: {}", e))?
+    } else {
+        Vec::new()
+    };
+
+    // Lines are edited as bytes so non-UTF8 entries are kept untouched
+    let mut lines: Vec<Vec<u8>> = content_bytes
+        .split_inclusive(|&byte| byte == b'\n')
+        .map(<[u8]>::to_vec)
+        .collect();
+
+    let mut data_header = None;
+    let mut last_entry = None;
+    // (line, key, position of '=') of the numbered [Data] entries
+    let mut entries = Vec::new();
+    let mut in_data = false;
+
+    for (index, line) in lines.iter().enumerate() {
+        let text = String::from_utf8_lossy(line);
+        let text = text.trim();
+
+        if text.starts_with('[') {
+            in_data = text.eq_ignore_ascii_case("[Data]");
+            if in_data && data_header.is_none() {
+                data_header = Some(index);
+            }
+            continue;
+        }
+        if !in_data || text.starts_with(';') || text.starts_with('#') {
+            continue;
+        }
+
+        let Some(separator) = line.iter().position(|&byte| byte == b'=') else { continue };
+        if String::from_utf8_lossy(&line[separator + 1..]).trim().eq_ignore_ascii_case(grf_name) {
+            return Ok(());
+        }
+        if let Ok(key) = String::from_utf8_lossy(&line[..separator]).trim().parse::<u32>() {
+            entries.push((index, key, separator));
+        }
+        last_entry = Some(index);
+    }
+
+    let data_header = match data_header {
+        Some(index) => index,
+        None => {
+            end_line(lines.last_mut());
+            lines.push(b"[Data]\r\n".to_vec());
+            lines.len() - 1
+        }
+    };
+
+    let (position, key) = if first {
+        for &(index, key, separator) in &entries {
+            lines[index] = [(key + 1).to_string().as_bytes(), &lines[index][separator..]].concat();
+        }
+        (data_header + 1, 0)
+    } else {
+        let next_key = entries.iter().map(|&(_, key, _)| key + 1).max().unwrap_or(0);
+        (last_entry.unwrap_or(data_header) + 1, next_key)
+    };
+
+    end_line(lines.get_mut(position - 1));
+    lines.insert(position, format!("{}={}\r\n", key, grf_name).into_bytes());
+
+    fs::write(&data_ini_path, lines.concat())
+        .map_err(|e| format!("Failed to write data.ini: {}", e))
+}
+
+/// Terminates a last line that has no line break, so a line can follow it.
+fn end_line(line: Option<&mut Vec<u8>>) {
+    if let Some(line) = line.filter(|line| !line.ends_with(b"\n")) {
+        line.extend_from_slice(b"\r\n");
+    }
+}
@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DownloadProgress {
@@ -9,40 +10,218 @@ pub struct DownloadProgress {
     pub percentage: f64,
 }
 
-pub async fn download_patch<F>(
+const MAX_RETRY_DELAY_SECS: u64 = 30;
+
+/// Size and SHA-256 the patch list announces for a file, checked while it downloads.
+#[derive(Debug, Clone, Default)]
+pub struct ExpectedFile {
+    pub size: Option<u64>,
+    /// Hex encoded, compared case-insensitively
+    pub sha256: Option<String>,
+}
+
+/// Sidecar of a `<dest>.part` file: which download it belongs to and the
+/// validator used to make sure the server still has the same file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct PartialDownload {
+    url: String,
+    size: Option<u64>,
+    sha256: Option<String>,
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+impl PartialDownload {
+    fn new(url: &str, expected: &ExpectedFile, response: &reqwest::Response) -> Self {
+        let header = |name| {
+            response.headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+        };
+
+        Self {
+            url: url.to_string(),
+            size: expected.size,
+            sha256: expected.sha256.clone(),
+            etag: header(reqwest::header::ETAG),
+            last_modified: header(reqwest::header::LAST_MODIFIED),
+        }
+    }
+
+    fn matches(&self, url: &str, expected: &ExpectedFile) -> bool {
+        self.url == url && self.size == expected.size && self.sha256 == expected.sha256
+    }
+
+    /// `If-Range` value: a strong ETag, else Last-Modified
+    fn validator(&self) -> Option<&str> {
+        self.etag.as_deref()
+            .filter(|etag| !etag.starts_with("W/"))
+            .or(self.last_modified.as_deref())
+    }
+}
+
+/// Returns the length of a `.part` left by an interrupted download of the
+/// same file, if it can be resumed safely.
+async fn resumable_length(part_path: &str, meta_path: &str, url: &str, expected: &ExpectedFile) -> Option<(PartialDownload, u64)> {
+    let meta: PartialDownload = serde_json::from_slice(&tokio::fs::read(meta_path).await.ok()?).ok()?;
+
+    // Without a validator or a hash there is no telling a stale prefix apart
+    if !meta.matches(url, expected) || (meta.validator().is_none() && expected.sha256.is_none()) {
+        return None;
+    }
+
+    let length = tokio::fs::metadata(part_path).await.ok()?.len();
+    if length == 0 || expected.size.is_some_and(|size| length >= size) {
+        return None;
+    }
+
+    Some((meta, length))
+}
+
+/// First byte of a `Content-Range: bytes <start>-<end>/<total>` header.
+fn content_range_start(response: &reqwest::Response) -> Option<u64> {
+    let value = response.headers().get(reqwest::header::CONTENT_RANGE)?.to_str().ok()?;
+    let range = value.trim().strip_prefix("bytes")?.trim_start();
+    range.split('-').next()?.trim().parse().ok()
+}
+
+/// Why a download attempt failed. Permanent failures (missing file, wrong
+/// size or hash) would fail the same way again on the same server, unless the
+/// bad data came from a resumed `.part` (`stale_part`).
+struct AttemptError {
+    message: String,
+    permanent: bool,
+    stale_part: bool,
+}
+
+impl From<String> for AttemptError {
+    fn from(message: String) -> Self {
+        Self { message, permanent: false, stale_part: false }
+    }
+}
+
+impl AttemptError {
+    fn permanent(message: String) -> Self {
+        Self { message, permanent: true, stale_part: false }
+    }
+
+    fn stale_part(message: String) -> Self {
+        Self { message, permanent: true, stale_part: true }
+    }
+}
+
+async fn download_attempt<F>(
     url: &str,
     dest_path: &str,
+    expected: &ExpectedFile,
     mut on_progress: F,
-) -> Result<(), String>
+) -> Result<(), AttemptError>
 where
     F: FnMut(DownloadProgress),
 {
+    let part_path = format!("{}.part", dest_path);
+    let meta_path = format!("{}.part.json", dest_path);
+    let resume = resumable_length(&part_path, &meta_path, url, expected).await;
+
     let client = reqwest::Client::new();
-    let response = client
-        .get(url)
+    let mut request = client.get(url);
+    if let Some((meta, offset)) = &resume {
+        request = request.header(reqwest::header::RANGE, format!("bytes={}-", offset));
+        if let Some(validator) = meta.validator() {
+            request = request.header(reqwest::header::IF_RANGE, validator);
+        }
+    }
+
+    let mut response = request
         .send()
         .await
         .map_err(|e| format!("Failed to start download: {}", e))?;
 
+    // 206 at the right offset continues the .part; a 200 means the server ignored
+    // the range or the file changed. Anything else is retried from scratch.
+    let mut offset = match &resume {
+        Some((_, offset)) if response.status() == reqwest::StatusCode::PARTIAL_CONTENT => {
+            if content_range_start(&response) == Some(*offset) { *offset } else { u64::MAX }
+        }
+        Some(_) if !response.status().is_success() => u64::MAX,
+        _ => 0,
+    };
+
+    if offset == u64::MAX {
+        println!("Cannot resume {}, downloading it again", url);
+        response = client
+            .get(url)
+            .send()
+            .await
+            .map_err(|e| format!("Failed to start download: {}", e))?;
+        offset = 0;
+    }
+
     if !response.status().is_success() {
-        return Err(format!("Download failed with status: {}", response.status()));
+        let message = format!("Download failed with status: {}", response.status());
+        return Err(if response.status().is_client_error() {
+            AttemptError::permanent(message)
+        } else {
+            message.into()
+        });
     }
 
-    let total_length = response.content_length().unwrap_or(0);
+    if let (Some(expected_size), Some(length)) = (expected.size, response.content_length()) {
+        if offset + length != expected_size {
+            return Err(AttemptError::permanent(format!(
+                "Server reports {} bytes, expected {}",
+                offset + length, expected_size
+            )));
+        }
+    }
+
+    let total_length = response.content_length().map(|length| offset + length).or(expected.size).unwrap_or(0);
     let filename = url.split('/').last().unwrap_or("unknown").to_string();
 
-    let mut file = tokio::fs::File::create(dest_path)
-        .await
-        .map_err(|e| format!("Failed to create file: {}", e))?;
+    use futures_util::StreamExt;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let mut hasher = Sha256::new();
+
+    let mut file = if offset > 0 {
+        // The hash covers the whole file, including what was downloaded before
+        let mut existing = tokio::fs::File::open(&part_path)
+            .await
+            .map_err(|e| format!("Failed to open partial download: {}", e))?;
+        let mut buffer = vec![0u8; 64 * 1024];
+        loop {
+            let read = existing.read(&mut buffer)
+                .await
+                .map_err(|e| format!("Failed to read partial download: {}", e))?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buffer[..read]);
+        }
+
+        tokio::fs::OpenOptions::new()
+            .append(true)
+            .open(&part_path)
+            .await
+            .map_err(|e| format!("Failed to open partial download: {}", e))?
+    } else {
+        let meta = serde_json::to_vec(&PartialDownload::new(url, expected, &response))
+            .map_err(|e| format!("Failed to serialize download state: {}", e))?;
+        tokio::fs::write(&meta_path, meta)
+            .await
+            .map_err(|e| format!("Failed to save download state: {}", e))?;
+
+        tokio::fs::File::create(&part_path)
+            .await
+            .map_err(|e| format!("Failed to create file: {}", e))?
+    };
 
-    let mut downloaded: u64 = 0;
+    let mut downloaded: u64 = offset;
     let mut last_time = std::time::Instant::now();
-    let mut last_downloaded: u64 = 0;
+    let mut last_downloaded: u64 = offset;
     let mut speed: f64 = 0.0;
 
-    use futures_util::StreamExt;
-    use tokio::io::AsyncWriteExt;
-
     let mut stream = response.bytes_stream();
 
     while let Some(chunk) = stream.next().await {
@@ -51,6 +230,7 @@ where
         file.write_all(&chunk)
             .await
             .map_err(|e| format!("Error writing to file: {}", e))?;
+        hasher.update(&chunk);
 
         downloaded += chunk.len() as u64;
 
@@ -82,13 +262,57 @@ where
     file.flush()
         .await
         .map_err(|e| format!("Failed to flush file: {}", e))?;
+    drop(file);
+
+    // The connection closed early: keep the .part for the next attempt
+    if downloaded < total_length {
+        return Err(format!("Download interrupted at {} of {} bytes", downloaded, total_length).into());
+    }
+
+    let verified = verify_download(expected, downloaded, hasher);
+
+    // A bad file must not be resumed or applied, a good one is moved in place
+    let _ = tokio::fs::remove_file(&meta_path).await;
+    if let Err(e) = verified {
+        let _ = tokio::fs::remove_file(&part_path).await;
+        return Err(if offset > 0 { AttemptError::stale_part(e) } else { AttemptError::permanent(e) });
+    }
+
+    tokio::fs::rename(&part_path, dest_path)
+        .await
+        .map_err(|e| format!("Failed to move downloaded file: {}", e).into())
+}
+
+fn verify_download(expected: &ExpectedFile, downloaded: u64, hasher: Sha256) -> Result<(), String> {
+    if let Some(expected_size) = expected.size {
+        if downloaded != expected_size {
+            return Err(format!(
+                "Size mismatch: expected {} bytes, got {}",
+                expected_size, downloaded
+            ));
+        }
+    }
+
+    if let Some(expected_hash) = &expected.sha256 {
+        let actual_hash = format!("{:x}", hasher.finalize());
+        if !actual_hash.eq_ignore_ascii_case(expected_hash.trim()) {
+            return Err(format!(
+                "Hash mismatch: expected {}, got {}",
+                expected_hash.trim(), actual_hash
+            ));
+        }
+    }
 
     Ok(())
 }
 
-pub async fn _download_with_retry<F>(
+/// Retries a download with exponential backoff. Interrupted attempts resume
+/// from the `.part` file left behind; permanent failures are returned at once,
+/// after restarting from scratch if the bad file was a resumed one.
+pub async fn download_with_retry<F>(
     url: &str,
     dest_path: &str,
+    expected: &ExpectedFile,
     on_progress: F,
     max_retries: u32,
 ) -> Result<(), String>
@@ -98,14 +322,24 @@ where
     let mut last_error = String::new();
 
     for attempt in 1..=max_retries {
-        match download_patch(url, dest_path, on_progress.clone()).await {
+        let mut result = download_attempt(url, dest_path, expected, on_progress.clone()).await;
+
+        // The .part that was resumed is gone now, so the server gets one more go from byte 0
+        if let Some(e) = result.as_ref().err().filter(|e| e.stale_part) {
+            eprintln!("Resumed download of {} is corrupt ({}), starting over", url, e.message);
+            result = download_attempt(url, dest_path, expected, on_progress.clone()).await;
+        }
+
+        match result {
             Ok(_) => return Ok(()),
+            Err(e) if e.permanent => return Err(e.message),
             Err(e) => {
-                last_error = e;
+                last_error = e.message;
                 eprintln!("Download attempt {} failed: {}", attempt, last_error);
 
                 if attempt < max_retries {
-                    tokio::time::sleep(tokio::time::Duration::from_secs(attempt as u64)).await;
+                    let delay = 2u64.pow(attempt - 1).min(MAX_RETRY_DELAY_SECS);
+                    tokio::time::sleep(tokio::time::Duration::from_secs(delay)).await;
                 }
             }
         }
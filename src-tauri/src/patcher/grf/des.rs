@@ -0,0 +1,293 @@
+//! RO's "DES": a single DES round with an all-zero key, applied to 8-byte blocks.
+//! Used by 0x1xx archives (file names and data) and by entries that carry
+//! `GRF_FLAG_ENCRYPT_MIXED` or `GRF_FLAG_ENCRYPT_HEADER`.
+
+use super::types::*;
+
+const BLOCK_SIZE: usize = 8;
+
+/// Leading blocks that are always encrypted
+const ENCRYPTED_HEAD_BLOCKS: usize = 20;
+
+/// Every 7th plaintext block of a mixed entry is shuffled instead
+const SHUFFLE_CYCLE: usize = 7;
+
+const MASK: [u8; 8] = [0x80, 0x40, 0x20, 0x10, 0x08, 0x04, 0x02, 0x01];
+
+const IP_TABLE: [u8; 64] = [
+    58, 50, 42, 34, 26, 18, 10, 2,
+    60, 52, 44, 36, 28, 20, 12, 4,
+    62, 54, 46, 38, 30, 22, 14, 6,
+    64, 56, 48, 40, 32, 24, 16, 8,
+    57, 49, 41, 33, 25, 17, 9, 1,
+    59, 51, 43, 35, 27, 19, 11, 3,
+    61, 53, 45, 37, 29, 21, 13, 5,
+    63, 55, 47, 39, 31, 23, 15, 7,
+];
+
+const FP_TABLE: [u8; 64] = [
+    40, 8, 48, 16, 56, 24, 64, 32,
+    39, 7, 47, 15, 55, 23, 63, 31,
+    38, 6, 46, 14, 54, 22, 62, 30,
+    37, 5, 45, 13, 53, 21, 61, 29,
+    36, 4, 44, 12, 52, 20, 60, 28,
+    35, 3, 43, 11, 51, 19, 59, 27,
+    34, 2, 42, 10, 50, 18, 58, 26,
+    33, 1, 41, 9, 49, 17, 57, 25,
+];
+
+const P_TABLE: [u8; 32] = [
+    16, 7, 20, 21,
+    29, 12, 28, 17,
+    1, 15, 23, 26,
+    5, 18, 31, 10,
+    2, 8, 24, 14,
+    32, 27, 3, 9,
+    19, 13, 30, 6,
+    22, 11, 4, 25,
+];
+
+/// Standard DES S-boxes, indexed by `row * 16 + column`
+const S_BOXES: [[u8; 64]; 8] = [
+    [
+        14, 4, 13, 1, 2, 15, 11, 8, 3, 10, 6, 12, 5, 9, 0, 7,
+        0, 15, 7, 4, 14, 2, 13, 1, 10, 6, 12, 11, 9, 5, 3, 8,
+        4, 1, 14, 8, 13, 6, 2, 11, 15, 12, 9, 7, 3, 10, 5, 0,
+        15, 12, 8, 2, 4, 9, 1, 7, 5, 11, 3, 14, 10, 0, 6, 13,
+    ],
+    [
+        15, 1, 8, 14, 6, 11, 3, 4, 9, 7, 2, 13, 12, 0, 5, 10,
+        3, 13, 4, 7, 15, 2, 8, 14, 12, 0, 1, 10, 6, 9, 11, 5,
+        0, 14, 7, 11, 10, 4, 13, 1, 5, 8, 12, 6, 9, 3, 2, 15,
+        13, 8, 10, 1, 3, 15, 4, 2, 11, 6, 7, 12, 0, 5, 14, 9,
+    ],
+    [
+        10, 0, 9, 14, 6, 3, 15, 5, 1, 13, 12, 7, 11, 4, 2, 8,
+        13, 7, 0, 9, 3, 4, 6, 10, 2, 8, 5, 14, 12, 11, 15, 1,
+        13, 6, 4, 9, 8, 15, 3, 0, 11, 1, 2, 12, 5, 10, 14, 7,
+        1, 10, 13, 0, 6, 9, 8, 7, 4, 15, 14, 3, 11, 5, 2, 12,
+    ],
+    [
+        7, 13, 14, 3, 0, 6, 9, 10, 1, 2, 8, 5, 11, 12, 4, 15,
+        13, 8, 11, 5, 6, 15, 0, 3, 4, 7, 2, 12, 1, 10, 14, 9,
+        10, 6, 9, 0, 12, 11, 7, 13, 15, 1, 3, 14, 5, 2, 8, 4,
+        3, 15, 0, 6, 10, 1, 13, 8, 9, 4, 5, 11, 12, 7, 2, 14,
+    ],
+    [
+        2, 12, 4, 1, 7, 10, 11, 6, 8, 5, 3, 15, 13, 0, 14, 9,
+        14, 11, 2, 12, 4, 7, 13, 1, 5, 0, 15, 10, 3, 9, 8, 6,
+        4, 2, 1, 11, 10, 13, 7, 8, 15, 9, 12, 5, 6, 3, 0, 14,
+        11, 8, 12, 7, 1, 14, 2, 13, 6, 15, 0, 9, 10, 4, 5, 3,
+    ],
+    [
+        12, 1, 10, 15, 9, 2, 6, 8, 0, 13, 3, 4, 14, 7, 5, 11,
+        10, 15, 4, 2, 7, 12, 9, 5, 6, 1, 13, 14, 0, 11, 3, 8,
+        9, 14, 15, 5, 2, 8, 12, 3, 7, 0, 4, 10, 1, 13, 11, 6,
+        4, 3, 2, 12, 9, 5, 15, 10, 11, 14, 1, 7, 6, 0, 8, 13,
+    ],
+    [
+        4, 11, 2, 14, 15, 0, 8, 13, 3, 12, 9, 7, 5, 10, 6, 1,
+        13, 0, 11, 7, 4, 9, 1, 10, 14, 3, 5, 12, 2, 15, 8, 6,
+        1, 4, 11, 13, 12, 3, 7, 14, 10, 15, 6, 8, 0, 5, 9, 2,
+        6, 11, 13, 8, 1, 4, 10, 7, 9, 5, 0, 15, 14, 2, 3, 12,
+    ],
+    [
+        13, 2, 8, 4, 6, 15, 11, 1, 10, 9, 3, 14, 5, 0, 12, 7,
+        1, 15, 13, 8, 10, 3, 7, 4, 12, 5, 6, 11, 0, 14, 9, 2,
+        7, 11, 4, 1, 9, 12, 14, 2, 0, 6, 10, 13, 15, 3, 5, 8,
+        2, 1, 14, 7, 4, 10, 8, 13, 15, 12, 9, 0, 3, 5, 6, 11,
+    ],
+];
+
+fn permute(block: &[u8; 8], table: &[u8; 64]) -> [u8; 8] {
+    let mut out = [0u8; 8];
+    for (i, &source) in table.iter().enumerate() {
+        let j = (source - 1) as usize;
+        if block[j >> 3] & MASK[j & 7] != 0 {
+            out[i >> 3] |= MASK[i & 7];
+        }
+    }
+    out
+}
+
+/// Expands the right half (bytes 4..8) into eight 6-bit groups.
+fn expand(block: &[u8; 8]) -> [u8; 8] {
+    let r = &block[4..8];
+    [
+        ((r[3] << 5) | (r[0] >> 3)) & 0x3f,
+        ((r[0] << 1) | (r[1] >> 7)) & 0x3f,
+        ((r[0] << 5) | (r[1] >> 3)) & 0x3f,
+        ((r[1] << 1) | (r[2] >> 7)) & 0x3f,
+        ((r[1] << 5) | (r[2] >> 3)) & 0x3f,
+        ((r[2] << 1) | (r[3] >> 7)) & 0x3f,
+        ((r[2] << 5) | (r[3] >> 3)) & 0x3f,
+        ((r[3] << 1) | (r[0] >> 7)) & 0x3f,
+    ]
+}
+
+/// Runs each 6-bit group through its S-box, packing two nibbles per byte.
+fn substitute(groups: &[u8; 8]) -> [u8; 4] {
+    let lookup = |index: usize| {
+        let group = groups[index];
+        let row = ((group >> 4) & 0x02) | (group & 0x01);
+        let column = (group >> 1) & 0x0f;
+        S_BOXES[index][(row * 16 + column) as usize]
+    };
+
+    [
+        (lookup(0) << 4) | lookup(1),
+        (lookup(2) << 4) | lookup(3),
+        (lookup(4) << 4) | lookup(5),
+        (lookup(6) << 4) | lookup(7),
+    ]
+}
+
+fn transpose(half: &[u8; 4]) -> [u8; 4] {
+    let mut out = [0u8; 4];
+    for (i, &source) in P_TABLE.iter().enumerate() {
+        let j = (source - 1) as usize;
+        if half[j >> 3] & MASK[j & 7] != 0 {
+            out[i >> 3] |= MASK[i & 7];
+        }
+    }
+    out
+}
+
+fn decrypt_block(block: &mut [u8]) {
+    let mut state: [u8; 8] = block.try_into().expect("DES blocks are 8 bytes");
+    state = permute(&state, &IP_TABLE);
+
+    let f = transpose(&substitute(&expand(&state)));
+    for (left, f) in state[..4].iter_mut().zip(f) {
+        *left ^= f;
+    }
+
+    block.copy_from_slice(&permute(&state, &FP_TABLE));
+}
+
+fn substitution(byte: u8) -> u8 {
+    match byte {
+        0x00 => 0x2B,
+        0x2B => 0x00,
+        0x6C => 0x80,
+        0x80 => 0x6C,
+        0x01 => 0x68,
+        0x68 => 0x01,
+        0x48 => 0x77,
+        0x77 => 0x48,
+        0x60 => 0xFF,
+        0xFF => 0x60,
+        0xB9 => 0xC0,
+        0xC0 => 0xB9,
+        0xFE => 0xEB,
+        0xEB => 0xFE,
+        other => other,
+    }
+}
+
+fn unshuffle_block(block: &mut [u8]) {
+    let src: [u8; 8] = (&*block).try_into().expect("DES blocks are 8 bytes");
+    block.copy_from_slice(&[
+        src[3],
+        src[4],
+        src[6],
+        src[0],
+        src[1],
+        src[2],
+        src[5],
+        substitution(src[7]),
+    ]);
+}
+
+/// Gap between two encrypted blocks of a mixed entry, derived from the number
+/// of decimal digits of its compressed size.
+fn mixed_cycle(compressed_size: i32) -> usize {
+    let mut digits = 1;
+    let mut limit = 10i64;
+    while limit <= compressed_size as i64 {
+        digits += 1;
+        limit *= 10;
+    }
+
+    match digits {
+        0..=2 => 1,
+        3..=4 => digits + 1,
+        5..=6 => digits + 9,
+        _ => digits + 15,
+    }
+}
+
+/// Decrypts an entry's aligned data in place according to its flags.
+pub fn decrypt_entry(data: &mut [u8], flags: u8, compressed_size: i32) {
+    let blocks = data.chunks_exact_mut(BLOCK_SIZE);
+
+    if flags & GRF_FLAG_ENCRYPT_MIXED != 0 {
+        let cycle = mixed_cycle(compressed_size);
+        let mut plain_blocks = 0;
+
+        for (index, block) in blocks.enumerate() {
+            if index < ENCRYPTED_HEAD_BLOCKS || index % cycle == 0 {
+                decrypt_block(block);
+                continue;
+            }
+
+            if plain_blocks % SHUFFLE_CYCLE == 0 && plain_blocks != 0 {
+                unshuffle_block(block);
+            }
+            plain_blocks += 1;
+        }
+    } else if flags & GRF_FLAG_ENCRYPT_HEADER != 0 {
+        for block in blocks.take(ENCRYPTED_HEAD_BLOCKS) {
+            decrypt_block(block);
+        }
+    }
+}
+
+/// Decodes a 0x1xx file table name: nibble-swapped, then DES-encrypted.
+pub fn decrypt_name(encoded: &[u8]) -> Vec<u8> {
+    let mut name: Vec<u8> = encoded.iter().map(|byte| byte.rotate_left(4)).collect();
+
+    for block in name.chunks_exact_mut(BLOCK_SIZE) {
+        decrypt_block(block);
+    }
+
+    if let Some(end) = name.iter().position(|&byte| byte == 0) {
+        name.truncate(end);
+    }
+    name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Expected values come from a textbook single-round, zero-key DES
+    #[test]
+    fn decrypt_block_known_answers() {
+        let cases: [([u8; 8], [u8; 8]); 3] = [
+            ([0x00; 8], [0x04, 0x04, 0x01, 0x55, 0x55, 0x01, 0x54, 0x55]),
+            ([0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08], [0x44, 0x16, 0x02, 0x51, 0x40, 0x06, 0x42, 0x5C]),
+            (*b"data\\a.s", [0x30, 0x25, 0x25, 0x24, 0x48, 0x20, 0x7B, 0x27]),
+        ];
+
+        for (input, expected) in cases {
+            let mut block = input;
+            decrypt_block(&mut block);
+            assert_eq!(block, expected);
+
+            // A single round without the swap is its own inverse
+            decrypt_block(&mut block);
+            assert_eq!(block, input);
+        }
+    }
+
+    #[test]
+    fn decrypt_name_known_answer() {
+        let encoded = [
+            0x17, 0x52, 0x53, 0x42, 0x85, 0x27, 0x52, 0x72,
+            0xD2, 0x03, 0x17, 0x91, 0x53, 0xB2, 0x63, 0x42,
+            0x67, 0x01, 0x11, 0x11, 0x55, 0x00, 0x54, 0x44,
+        ];
+        assert_eq!(decrypt_name(&encoded), b"data\\sprite\\a.spr");
+    }
+}
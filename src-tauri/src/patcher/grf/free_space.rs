@@ -0,0 +1,81 @@
+use super::types::*;
+use std::collections::HashMap;
+
+/// Free-space map for QuickMerge, modelled after GRF Editor's `QuickMergeHelper`.
+///
+/// Holes are the gaps between live entries (sorted by offset). New data goes
+/// into the smallest hole that fits and is only appended at the end of the data
+/// area when none does. Entries replaced or removed by a merge stay untouched
+/// until it is committed, so a journal rollback never finds their data
+/// overwritten; the next merge picks their space up as ordinary holes.
+/// All offsets are absolute (header included).
+pub struct FreeSpaceMap {
+    /// (offset, length) pairs sorted by offset
+    holes: Vec<(u64, u64)>,
+    end_of_data: u64,
+}
+
+impl FreeSpaceMap {
+    pub fn from_table(table: &HashMap<Vec<u8>, GrfEntry>) -> Self {
+        // Only files occupy space, a directory entry may carry any offset
+        let mut extents: Vec<(u64, u64)> = table
+            .values()
+            .filter(|entry| entry.is_file())
+            .map(|entry| (GRF_HEADER_SIZE as u64 + entry.offset, entry.compressed_size_aligned as u64))
+            .collect();
+        extents.sort_unstable();
+
+        let mut holes = Vec::new();
+        let mut cursor = GRF_HEADER_SIZE as u64;
+
+        for (offset, length) in extents {
+            if offset > cursor {
+                holes.push((cursor, offset - cursor));
+            }
+            cursor = cursor.max(offset + length);
+        }
+
+        Self {
+            holes,
+            end_of_data: cursor,
+        }
+    }
+
+    /// Absolute offset where the data area currently ends (the file table goes here).
+    pub fn end_of_data(&self) -> u64 {
+        self.end_of_data
+    }
+
+    /// Reserves `length` bytes at the end of the data area, for data whose size
+    /// was not known before it was written there.
+    pub fn append(&mut self, length: u64) -> u64 {
+        let offset = self.end_of_data;
+        self.end_of_data += length;
+        offset
+    }
+
+    /// Reserves `length` bytes, preferring the smallest hole that fits.
+    /// Returns the absolute offset the data must be written at.
+    pub fn allocate(&mut self, length: u64) -> u64 {
+        let best_fit = self
+            .holes
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, hole_length))| *hole_length >= length)
+            .min_by_key(|(_, (_, hole_length))| *hole_length)
+            .map(|(index, _)| index);
+
+        match best_fit {
+            Some(index) => {
+                let (hole_offset, hole_length) = self.holes[index];
+                if hole_length == length {
+                    self.holes.remove(index);
+                } else {
+                    self.holes[index] = (hole_offset + length, hole_length - length);
+                }
+                hole_offset
+            }
+            None => self.append(length),
+        }
+    }
+}
@@ -0,0 +1,160 @@
+use super::types::*;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+const JOURNAL_MAGIC: &[u8; 8] = b"KAFRAJNL";
+const JOURNAL_SUFFIX: &str = ".journal";
+
+/// Write-ahead journal for QuickMerge.
+///
+/// Before a GRF is modified, everything the merge may overwrite is saved next
+/// to it: the original length, the header and the old file table (new data is
+/// written where the old table lived). The journal is deleted once the merge
+/// is on disk; if one is still present on startup the merge never finished and
+/// the GRF is rolled back to its previous state.
+pub struct GrfJournal {
+    path: String,
+}
+
+impl GrfJournal {
+    pub fn begin(grf_path: &str, header: &GrfHeader) -> Result<Self, String> {
+        let mut file = File::open(grf_path)
+            .map_err(|e| format!("Failed to open GRF: {}", e))?;
+
+        let file_length = file.metadata()
+            .map_err(|e| format!("Failed to read GRF size: {}", e))?
+            .len();
+
+        let mut header_bytes = vec![0u8; GRF_HEADER_SIZE];
+        file.read_exact(&mut header_bytes)
+            .map_err(|e| format!("Failed to read header: {}", e))?;
+
+        let table_offset = (GRF_HEADER_SIZE as u64 + header.file_table_offset).min(file_length);
+        file.seek(SeekFrom::Start(table_offset))
+            .map_err(|e| format!("Failed to seek to table: {}", e))?;
+
+        let mut table_bytes = Vec::new();
+        file.read_to_end(&mut table_bytes)
+            .map_err(|e| format!("Failed to read table: {}", e))?;
+
+        let mut journal = Vec::with_capacity(table_bytes.len() + 96);
+        journal.extend_from_slice(JOURNAL_MAGIC);
+        journal.extend_from_slice(&file_length.to_le_bytes());
+        journal.extend_from_slice(&header_bytes);
+        journal.extend_from_slice(&table_offset.to_le_bytes());
+        journal.extend_from_slice(&(table_bytes.len() as u64).to_le_bytes());
+        journal.extend_from_slice(&table_bytes);
+        // Trailing magic marks the journal as complete
+        journal.extend_from_slice(JOURNAL_MAGIC);
+
+        let path = journal_path(grf_path);
+        let mut journal_file = File::create(&path)
+            .map_err(|e| format!("Failed to create GRF journal: {}", e))?;
+        journal_file.write_all(&journal)
+            .map_err(|e| format!("Failed to write GRF journal: {}", e))?;
+        journal_file.sync_all()
+            .map_err(|e| format!("Failed to flush GRF journal: {}", e))?;
+
+        Ok(Self { path })
+    }
+
+    /// Marks the merge as complete. The GRF must be flushed to disk before.
+    pub fn commit(self) -> Result<(), String> {
+        fs::remove_file(&self.path)
+            .map_err(|e| format!("Failed to remove GRF journal: {}", e))
+    }
+}
+
+fn journal_path(grf_path: &str) -> String {
+    format!("{}{}", grf_path, JOURNAL_SUFFIX)
+}
+
+/// Rolls `grf_path` back if an unfinished merge left a journal behind.
+/// Returns true when a rollback happened.
+pub fn recover(grf_path: &str) -> Result<bool, String> {
+    let path = journal_path(grf_path);
+    let journal = match fs::read(&path) {
+        Ok(journal) => journal,
+        Err(_) => return Ok(false),
+    };
+
+    // An incomplete journal means the GRF was never touched
+    let (file_length, header_bytes, table_offset, table_bytes) = match parse_journal(&journal) {
+        Some(parsed) => parsed,
+        None => {
+            fs::remove_file(&path)
+                .map_err(|e| format!("Failed to remove GRF journal: {}", e))?;
+            return Ok(false);
+        }
+    };
+
+    let mut file = OpenOptions::new()
+        .write(true)
+        .open(grf_path)
+        .map_err(|e| format!("Failed to open GRF for rollback: {}", e))?;
+
+    file.set_len(file_length)
+        .map_err(|e| format!("Failed to restore GRF size: {}", e))?;
+    file.seek(SeekFrom::Start(table_offset))
+        .map_err(|e| format!("Failed to seek to table: {}", e))?;
+    file.write_all(table_bytes)
+        .map_err(|e| format!("Failed to restore file table: {}", e))?;
+    file.seek(SeekFrom::Start(0))
+        .map_err(|e| format!("Failed to seek to header: {}", e))?;
+    file.write_all(header_bytes)
+        .map_err(|e| format!("Failed to restore header: {}", e))?;
+    file.sync_all()
+        .map_err(|e| format!("Failed to flush GRF: {}", e))?;
+
+    fs::remove_file(&path)
+        .map_err(|e| format!("Failed to remove GRF journal: {}", e))?;
+
+    Ok(true)
+}
+
+/// Recovers every GRF in `dir` that has a journal next to it.
+/// Returns the names of the GRFs that were rolled back.
+pub fn recover_all(dir: &Path) -> Result<Vec<String>, String> {
+    let mut recovered = Vec::new();
+
+    let entries = fs::read_dir(dir)
+        .map_err(|e| format!("Failed to list {}: {}", dir.display(), e))?;
+
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if let Some(grf_name) = name.strip_suffix(JOURNAL_SUFFIX) {
+            let grf_path = dir.join(grf_name);
+            if recover(&grf_path.to_string_lossy())? {
+                recovered.push(grf_name.to_string());
+            }
+        }
+    }
+
+    Ok(recovered)
+}
+
+fn parse_journal(journal: &[u8]) -> Option<(u64, &[u8], u64, &[u8])> {
+    let read_u64 = |at: usize| -> Option<u64> {
+        Some(u64::from_le_bytes(journal.get(at..at + 8)?.try_into().ok()?))
+    };
+
+    if journal.get(0..8)? != JOURNAL_MAGIC {
+        return None;
+    }
+
+    let file_length = read_u64(8)?;
+    let header_bytes = journal.get(16..16 + GRF_HEADER_SIZE)?;
+    let mut pos = 16 + GRF_HEADER_SIZE;
+    let table_offset = read_u64(pos)?;
+    let table_length = read_u64(pos + 8)? as usize;
+    pos += 16;
+    let table_bytes = journal.get(pos..pos.checked_add(table_length)?)?;
+    pos += table_length;
+
+    if journal.get(pos..)? != JOURNAL_MAGIC {
+        return None;
+    }
+
+    Some((file_length, header_bytes, table_offset, table_bytes))
+}
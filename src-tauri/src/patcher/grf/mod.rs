@@ -1,4 +1,7 @@
 pub mod types;
+pub mod des;
+pub mod free_space;
+pub mod journal;
 pub mod reader;
 pub mod writer;
 
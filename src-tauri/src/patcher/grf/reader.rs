@@ -1,15 +1,23 @@
+use super::des;
 use super::types::*;
+use flate2::read::ZlibDecoder;
+use std::cell::OnceCell;
 use std::collections::HashMap;
 use std::fs::File;
-use std::io::{Read, Seek, SeekFrom};
+use std::io::{BufReader, Cursor, Read, Seek, SeekFrom, Take};
 
 pub struct GrfReader {
     file_path: String,
+    /// File table, loaded on the first entry lookup
+    entries: OnceCell<HashMap<Vec<u8>, GrfEntry>>,
 }
 
 impl GrfReader {
     pub fn new(file_path: String) -> Self {
-        Self { file_path }
+        Self {
+            file_path,
+            entries: OnceCell::new(),
+        }
     }
 
     pub fn read_header(&self) -> Result<GrfHeader, String> {
@@ -26,8 +34,8 @@ impl GrfReader {
             return Err("Invalid GRF signature".to_string());
         }
 
-        // Key (14 bytes)
-        let key = buffer[15..29].to_vec();
+        // Key (14 bytes at 0x10)
+        let key = buffer[16..30].to_vec();
 
         // Version (4 bytes at 0x2A)
         let version_offset = 0x2A;
@@ -38,6 +46,7 @@ impl GrfReader {
         let major_version = (version >> 8) as u8;
 
         let file_table_offset;
+        let seed;
         let real_file_count;
 
         // Header parsing logic based on version
@@ -47,7 +56,8 @@ impl GrfReader {
                 buffer[30], buffer[31], buffer[32], buffer[33],
                 buffer[34], buffer[35], buffer[36], buffer[37],
             ]);
-            // v3.0: RealFilesCount at 0x22 (previously seed)
+            // v3.0: RealFilesCount at 0x26, there is no seed
+            seed = 0;
             real_file_count = i32::from_le_bytes([buffer[38], buffer[39], buffer[40], buffer[41]]);
         } else {
             // v1.x / v2.0: Offset is 4 bytes at 0x1E
@@ -55,27 +65,38 @@ impl GrfReader {
             file_table_offset = raw_offset as u64;
 
             // Seed at 0x22
-            let seed = i32::from_le_bytes([buffer[38], buffer[39], buffer[40], buffer[41]]);
+            seed = i32::from_le_bytes([buffer[34], buffer[35], buffer[36], buffer[37]]);
             // FilesCount at 0x26
-            let raw_file_count = i32::from_le_bytes([buffer[42], buffer[43], buffer[44], buffer[45]]);
+            let raw_file_count = i32::from_le_bytes([buffer[38], buffer[39], buffer[40], buffer[41]]);
             
             real_file_count = raw_file_count - seed - 7;
         }
 
         Ok(GrfHeader {
             _signature: signature,
-            _key: key,
+            key,
             file_table_offset,
+            seed,
             real_file_count,
-            _version: version,
+            version,
         })
     }
 
-    pub fn read_file_table(&self, header: &GrfHeader) -> Result<HashMap<String, GrfEntry>, String> {
+    pub fn read_file_table(&self, header: &GrfHeader) -> Result<HashMap<Vec<u8>, GrfEntry>, String> {
+        if header.is_legacy() {
+            return self.read_legacy_file_table(header);
+        }
+
         let mut file = File::open(&self.file_path)
             .map_err(|e| format!("Failed to open GRF: {}", e))?;
 
-        let absolute_table_offset = header.file_table_offset + GRF_HEADER_SIZE as u64;
+        let mut absolute_table_offset = header.file_table_offset + GRF_HEADER_SIZE as u64;
+
+        // v3.0 tables carry 4 extra bytes before the compressed table sizes
+        if header.is_v3() {
+            absolute_table_offset += GRF_V3_TABLE_PREFIX_SIZE as u64;
+        }
+
         file.seek(SeekFrom::Start(absolute_table_offset))
             .map_err(|e| format!("Failed to seek to table: {}", e))?;
 
@@ -93,7 +114,6 @@ impl GrfReader {
             .map_err(|e| format!("Failed to read compressed table: {}", e))?;
 
         // Decompress using flate2
-        use flate2::read::ZlibDecoder;
         let mut decoder = ZlibDecoder::new(&compressed_data[..]);
         let mut data = Vec::new();
         decoder.read_to_end(&mut data)
@@ -104,6 +124,9 @@ impl GrfReader {
         let mut offset = 0;
         let mut file_count = 0;
 
+        // v3.0 entries store a 64-bit data offset, older versions a 32-bit one
+        let entry_offset_size = if header.is_v3() { 8 } else { 4 };
+
         while offset < data.len() && file_count < header.real_file_count {
             // Read filename (null-terminated)
             let mut end_name = offset;
@@ -111,10 +134,10 @@ impl GrfReader {
                 end_name += 1;
             }
 
-            let filename = String::from_utf8_lossy(&data[offset..end_name]).to_string();
+            let filename = data[offset..end_name].to_vec();
             offset = end_name + 1;
 
-            if offset + 17 > data.len() {
+            if offset + 13 + entry_offset_size > data.len() {
                 break;
             }
 
@@ -126,11 +149,18 @@ impl GrfReader {
             offset += 4;
             let entry_flags = data[offset];
             offset += 1;
-            let entry_offset = i32::from_le_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]]);
-            offset += 4;
+            let entry_offset = if header.is_v3() {
+                u64::from_le_bytes([
+                    data[offset], data[offset + 1], data[offset + 2], data[offset + 3],
+                    data[offset + 4], data[offset + 5], data[offset + 6], data[offset + 7],
+                ])
+            } else {
+                u32::from_le_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]]) as u64
+            };
+            offset += entry_offset_size;
 
             entries.insert(
-                filename.to_lowercase(),
+                grf_key(&filename),
                 GrfEntry {
                     filename,
                     compressed_size: entry_compressed_size,
@@ -138,8 +168,6 @@ impl GrfReader {
                     real_size: entry_real_size,
                     flags: entry_flags,
                     offset: entry_offset,
-                    _is_new: false,
-                    _data: None,
                 },
             );
 
@@ -148,4 +176,138 @@ impl GrfReader {
 
         Ok(entries)
     }
+
+    /// 0x1xx tables are stored uncompressed up to the end of the file, with
+    /// DES-encoded names and obfuscated sizes. Every file in them is encrypted.
+    fn read_legacy_file_table(&self, header: &GrfHeader) -> Result<HashMap<Vec<u8>, GrfEntry>, String> {
+        let mut file = File::open(&self.file_path)
+            .map_err(|e| format!("Failed to open GRF: {}", e))?;
+
+        file.seek(SeekFrom::Start(header.file_table_offset + GRF_HEADER_SIZE as u64))
+            .map_err(|e| format!("Failed to seek to table: {}", e))?;
+
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)
+            .map_err(|e| format!("Failed to read table: {}", e))?;
+
+        let read_i32 = |at: usize| -> Option<i32> {
+            Some(i32::from_le_bytes(data.get(at..at + 4)?.try_into().ok()?))
+        };
+
+        let mut entries = HashMap::new();
+        let mut offset = 0;
+
+        for _ in 0..header.real_file_count.max(0) {
+            let Some(name_length) = read_i32(offset) else { break };
+            // Tables come from downloaded patches too, so a bogus length ends the table
+            let name_length = name_length.max(0) as usize;
+            let Some(attributes) = offset.checked_add(4 + name_length) else { break };
+            if attributes.checked_add(17).is_none_or(|end| end > data.len()) {
+                break;
+            }
+
+            let entry_flags = data[attributes + 12];
+            if entry_flags & GRF_FLAG_FILE != 0 {
+                // The encoded name skips two bytes and is decoded in whole 8-byte blocks
+                let name_start = (offset + 6).min(attributes);
+                let Some(name_end) = name_length.saturating_sub(6)
+                    .checked_next_multiple_of(8)
+                    .and_then(|length| name_start.checked_add(length))
+                else {
+                    break;
+                };
+                let filename = des::decrypt_name(&data[name_start..name_end.min(data.len())]);
+
+                let stored_compressed = read_i32(attributes).unwrap_or(0);
+                let stored_aligned = read_i32(attributes + 4).unwrap_or(0);
+                let real_size = read_i32(attributes + 8).unwrap_or(0);
+                let stored_offset = read_i32(attributes + 13).unwrap_or(0);
+
+                entries.insert(
+                    grf_key(&filename),
+                    GrfEntry {
+                        compressed_size: stored_compressed - real_size - 715,
+                        compressed_size_aligned: stored_aligned - 37579,
+                        real_size,
+                        flags: entry_flags | legacy_encryption(&filename),
+                        offset: stored_offset as u32 as u64,
+                        filename,
+                    },
+                );
+            }
+
+            offset = attributes + 17;
+        }
+
+        Ok(entries)
+    }
+
+    /// Looks up an entry by its raw CP949 path (case-insensitive, `/` or `\\` separators).
+    pub fn get_entry(&self, name: impl AsRef<[u8]>) -> Result<&GrfEntry, String> {
+        let name = name.as_ref();
+        if self.entries.get().is_none() {
+            let header = self.read_header()?;
+            let table = self.read_file_table(&header)?;
+            let _ = self.entries.set(table);
+        }
+
+        self.entries
+            .get()
+            .and_then(|entries| entries.get(&grf_key(name)))
+            .ok_or_else(|| format!("{} not found in GRF", decode_name(name)))
+    }
+
+    /// Opens a streaming reader that inflates `entry` while it is read.
+    pub fn open_entry(&self, entry: &GrfEntry) -> Result<GrfEntryReader, String> {
+        if !entry.is_file() {
+            return Err(format!("{} is not a file", entry.display_name()));
+        }
+
+        let mut file = File::open(&self.file_path)
+            .map_err(|e| format!("Failed to open GRF: {}", e))?;
+
+        file.seek(SeekFrom::Start(GRF_HEADER_SIZE as u64 + entry.offset))
+            .map_err(|e| format!("Failed to seek to {}: {}", entry.display_name(), e))?;
+
+        let compressed: Box<dyn Read + Send> = if entry.is_encrypted() {
+            // Encrypted blocks are spread over the whole entry, so it is decoded up front
+            let mut data = vec![0u8; entry.compressed_size_aligned.max(0) as usize];
+            file.read_exact(&mut data)
+                .map_err(|e| format!("Failed to read {}: {}", entry.display_name(), e))?;
+
+            des::decrypt_entry(&mut data, entry.flags, entry.compressed_size);
+            data.truncate(entry.compressed_size.max(0) as usize);
+
+            Box::new(Cursor::new(data))
+        } else {
+            Box::new(BufReader::new(file).take(entry.compressed_size_aligned as u64))
+        };
+
+        Ok(GrfEntryReader {
+            decoder: ZlibDecoder::new(compressed).take(entry.real_size as u64),
+        })
+    }
+}
+
+/// 0x1xx archives encrypt map data headers only and everything else in mixed mode.
+fn legacy_encryption(filename: &[u8]) -> u8 {
+    const HEADER_ONLY: [&[u8]; 4] = [b".gnd", b".gat", b".act", b".str"];
+
+    let lowercase = filename.to_ascii_lowercase();
+    if HEADER_ONLY.iter().any(|extension| lowercase.ends_with(extension)) {
+        GRF_FLAG_ENCRYPT_HEADER
+    } else {
+        GRF_FLAG_ENCRYPT_MIXED
+    }
+}
+
+/// Streaming reader over the inflated contents of a GRF entry.
+pub struct GrfEntryReader {
+    decoder: Take<ZlibDecoder<Box<dyn Read + Send>>>,
+}
+
+impl Read for GrfEntryReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.decoder.read(buf)
+    }
 }
@@ -1,23 +1,110 @@
+use serde::{Deserialize, Serialize};
+
 pub const GRF_HEADER_SIZE: usize = 46;
 pub const GRF_SIGNATURE: &str = "Master of Magic";
+pub const GRF_V3_TABLE_PREFIX_SIZE: usize = 4;
+
+// Entry flags
+pub const GRF_FLAG_FILE: u8 = 0x01;
+pub const GRF_FLAG_ENCRYPT_MIXED: u8 = 0x02;
+pub const GRF_FLAG_ENCRYPT_HEADER: u8 = 0x04;
+/// Patch archives (GPF/THOR) mark entries to delete from the target with this value
+pub const GRF_FLAG_REMOVE: u8 = 0x05;
 
 #[derive(Debug, Clone)]
 pub struct GrfHeader {
     pub _signature: String,
-    pub _key: Vec<u8>,
+    pub key: Vec<u8>,
     pub file_table_offset: u64,
+    pub seed: i32,
     pub real_file_count: i32,
-    pub _version: u32,
+    pub version: u32,
+}
+
+impl GrfHeader {
+    pub fn major_version(&self) -> u8 {
+        (self.version >> 8) as u8
+    }
+
+    /// 0x102/0x103 archives have an uncompressed, DES-encoded file table.
+    pub fn is_legacy(&self) -> bool {
+        self.major_version() == 1
+    }
+
+    /// 0x300 archives use 64-bit offsets in both the header and the file table.
+    pub fn is_v3(&self) -> bool {
+        self.major_version() == 3
+    }
+}
+
+/// Table lookup key: GRF paths are case-insensitive and use backslashes.
+/// Names are raw CP949 bytes: only ASCII characters are folded, the trail
+/// byte of a double-byte character is kept even when it falls in A-Z.
+pub fn grf_key(filename: &[u8]) -> Vec<u8> {
+    let mut key = Vec::with_capacity(filename.len());
+    let mut bytes = filename.iter();
+
+    while let Some(&byte) = bytes.next() {
+        if byte >= 0x81 {
+            key.push(byte);
+            key.extend(bytes.next());
+        } else if byte == b'/' {
+            key.push(b'\\');
+        } else {
+            key.push(byte.to_ascii_lowercase());
+        }
+    }
+
+    key
+}
+
+/// GRF and THOR names are stored in CP949 (EUC-KR); this decodes them for
+/// display and for paths on disk.
+pub fn decode_name(raw: &[u8]) -> String {
+    encoding_rs::EUC_KR.decode_without_bom_handling(raw).0.into_owned()
+}
+
+/// Converts a name that may have been written as UTF-8 (e.g. by a modern ZIP
+/// tool) to the CP949 bytes the client looks up. Anything else is kept as is.
+pub fn encode_name(raw: &[u8]) -> Vec<u8> {
+    match std::str::from_utf8(raw) {
+        Ok(name) if !name.is_ascii() => {
+            let (encoded, _, had_errors) = encoding_rs::EUC_KR.encode(name);
+            if had_errors { raw.to_vec() } else { encoded.into_owned() }
+        }
+        _ => raw.to_vec(),
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct GrfEntry {
-    pub filename: String,
+    /// Raw name as stored in the table (CP949)
+    pub filename: Vec<u8>,
     pub compressed_size: i32,
     pub compressed_size_aligned: i32,
     pub real_size: i32,
     pub flags: u8,
-    pub offset: i32,
-    pub _is_new: bool,
-    pub _data: Option<Vec<u8>>,
+    pub offset: u64,
+}
+
+impl GrfEntry {
+    pub fn display_name(&self) -> String {
+        decode_name(&self.filename)
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.flags & GRF_FLAG_FILE != 0
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.flags & (GRF_FLAG_ENCRYPT_MIXED | GRF_FLAG_ENCRYPT_HEADER) != 0
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RepackProgress {
+    pub grf: String,
+    pub current: u32,
+    pub total: u32,
+    pub percentage: f64,
 }
@@ -1,7 +1,11 @@
+use super::free_space::FreeSpaceMap;
+use super::journal::{self, GrfJournal};
 use super::types::*;
+use super::reader::GrfReader;
 use std::collections::HashMap;
-use std::fs::OpenOptions;
-use std::io::{Write, Seek, SeekFrom};
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write, Seek, SeekFrom};
+use std::path::Path;
 
 pub struct GrfWriter;
 
@@ -10,128 +14,472 @@ impl GrfWriter {
         Self
     }
 
-    /// QuickMerge: Append new files to existing GRF without full repack
-    pub fn quick_merge(
+    /// Create: Write an empty archive (header and empty file table) from scratch.
+    /// Only 0x200 and 0x300 archives can be created.
+    pub fn create(&self, grf_path: &str, version: u32) -> Result<(), String> {
+        if version != 0x200 && version != 0x300 {
+            return Err(format!("Cannot create GRF version 0x{:X}", version));
+        }
+
+        let header = GrfHeader {
+            _signature: GRF_SIGNATURE.to_string(),
+            key: (1..=14).collect(),
+            file_table_offset: 0,
+            seed: 0,
+            real_file_count: 0,
+            version,
+        };
+
+        let mut file = File::create(grf_path)
+            .map_err(|e| format!("Failed to create GRF: {}", e))?;
+
+        file.seek(SeekFrom::Start(GRF_HEADER_SIZE as u64))
+            .map_err(|e| format!("Failed to seek past header: {}", e))?;
+        write_file_table(&mut file, &header, &HashMap::new())?;
+        write_header(&mut file, &header, 0, 0)?;
+
+        file.sync_all()
+            .map_err(|e| format!("Failed to flush GRF: {}", e))
+    }
+
+    /// Opens an incremental QuickMerge: entries are added or removed one at a
+    /// time and the new table is only written by `GrfMerge::finish`.
+    pub fn begin_merge(
         &self,
         grf_path: &str,
-        _header: &GrfHeader,
-        mut table: HashMap<String, GrfEntry>,
-        new_files: HashMap<String, Vec<u8>>,
-        _deletions: std::collections::HashSet<String>,
-    ) -> Result<(), String> {
-        let mut file = OpenOptions::new()
+        header: &GrfHeader,
+        table: HashMap<Vec<u8>, GrfEntry>,
+    ) -> Result<GrfMerge, String> {
+        if header.is_legacy() {
+            return Err(format!("Cannot merge into a 0x{:X} GRF, its file table is read-only", header.version));
+        }
+
+        let file = OpenOptions::new()
             .read(true)
             .write(true)
             .open(grf_path)
             .map_err(|e| format!("Failed to open GRF for writing: {}", e))?;
 
-        // Seek to end to append new file data
-        let current_end = file.seek(SeekFrom::End(0))
-            .map_err(|e| format!("Failed to seek to end: {}", e))?;
-
-        let mut data_offset = current_end;
-
-        // Write new file data
-        for (filename, data) in &new_files {
-            let normalized = filename.replace('\\', "/");
-            
-            // Compress data using ZLIB
-            use flate2::write::ZlibEncoder;
-            use flate2::Compression;
-            
-            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
-            encoder.write_all(data)
-                .map_err(|e| format!("Failed to compress file {}: {}", filename, e))?;
-            let compressed_data = encoder.finish()
-                .map_err(|e| format!("Failed to finish compression for {}: {}", filename, e))?;
-
-            let compressed_size = compressed_data.len() as i32;
-            let real_size = data.len() as i32;
-
-            // Calculate aligned size (8-byte alignment)
-            let aligned_size = (compressed_size + 7) & !7;
-            let padding = aligned_size - compressed_size;
-
-            let entry = GrfEntry {
-                filename: normalized.clone(),
-                compressed_size,
-                compressed_size_aligned: aligned_size,
-                real_size,
-                flags: 0x01, // FILE flag
-                offset: (data_offset - GRF_HEADER_SIZE as u64) as i32,
-                _is_new: true,
-                _data: Some(data.clone()),
-            };
-
-            // Write compressed data
-            file.write_all(&compressed_data)
-                .map_err(|e| format!("Failed to write file data: {}", e))?;
-
-            // Write padding if needed
-            if padding > 0 {
-                let pad_bytes = vec![0u8; padding as usize];
-                file.write_all(&pad_bytes)
-                    .map_err(|e| format!("Failed to write padding: {}", e))?;
-            }
+        // Holes between entries are reused, the rest goes after the last live entry
+        let free_space = FreeSpaceMap::from_table(&table);
+
+        // Nothing is written before the old header and table are safe in the journal
+        let journal = GrfJournal::begin(grf_path, header)?;
+
+        Ok(GrfMerge {
+            grf_path: grf_path.to_string(),
+            file,
+            header: header.clone(),
+            table,
+            free_space,
+            journal: Some(journal),
+        })
+    }
 
-            table.insert(normalized.to_lowercase(), entry);
-            data_offset += aligned_size as u64;
+    /// Repack: Stream every live entry into a fresh GRF in offset order, dropping
+    /// holes, stale tables and duplicated data, then swap it over the original.
+    pub fn repack<F>(&self, grf_path: &str, mut on_progress: F) -> Result<(), String>
+    where
+        F: FnMut(RepackProgress),
+    {
+        journal::recover(grf_path)?;
+
+        let reader = GrfReader::new(grf_path.to_string());
+        let header = reader.read_header()?;
+        if header.is_legacy() {
+            return Err(format!("Cannot repack a 0x{:X} GRF", header.version));
         }
+        let mut table = reader.read_file_table(&header)?;
 
-        // Rebuild file table
-        let mut table_data = Vec::new();
-        for entry in table.values() {
-            // Write filename (null-terminated)
-            table_data.extend_from_slice(entry.filename.as_bytes());
-            table_data.push(0);
-
-            // Write entry fields
-            table_data.extend_from_slice(&entry.compressed_size.to_le_bytes());
-            table_data.extend_from_slice(&entry.compressed_size_aligned.to_le_bytes());
-            table_data.extend_from_slice(&entry.real_size.to_le_bytes());
-            table_data.push(entry.flags);
-            table_data.extend_from_slice(&entry.offset.to_le_bytes());
+        let mut source = File::open(grf_path)
+            .map_err(|e| format!("Failed to open GRF: {}", e))?;
+
+        let temp_path = format!("{}.tmp", grf_path);
+        let mut target = File::create(&temp_path)
+            .map_err(|e| format!("Failed to create temporary GRF: {}", e))?;
+
+        let grf_name = Path::new(grf_path)
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        let result = write_repacked(&mut source, &mut target, &header, &mut table, &grf_name, &mut on_progress);
+
+        drop(source);
+        drop(target);
+
+        if let Err(e) = result {
+            let _ = fs::remove_file(&temp_path);
+            return Err(e);
         }
 
-        // Compress table
+        fs::rename(&temp_path, grf_path).map_err(|e| {
+            let _ = fs::remove_file(&temp_path);
+            format!("Failed to replace GRF: {}", e)
+        })
+    }
+}
+
+/// Compressed data is kept in memory up to this size; larger entries are
+/// streamed to the end of the data area instead of into a hole.
+const SPILL_THRESHOLD: usize = 4 * 1024 * 1024;
+
+/// An open QuickMerge. The old file table is overwritten and the file is
+/// truncated to the new table's end on `finish`. The previous header and table
+/// are journaled, and a merge dropped without finishing is rolled back.
+pub struct GrfMerge {
+    grf_path: String,
+    file: File,
+    header: GrfHeader,
+    table: HashMap<Vec<u8>, GrfEntry>,
+    free_space: FreeSpaceMap,
+    journal: Option<GrfJournal>,
+}
+
+impl GrfMerge {
+    /// Removes an entry. Its space is only reused by the next merge, so a
+    /// rollback never finds it overwritten.
+    pub fn remove_file(&mut self, filename: &[u8]) {
+        self.table.remove(&grf_key(filename));
+    }
+
+    /// Compresses `reader` into the GRF as `filename` (raw CP949 path), replacing
+    /// any entry with the same name. Entries always replace old ones as plain
+    /// zlib data, so an encrypted entry loses its encryption flags once patched.
+    pub fn add_file<R: Read>(&mut self, filename: &[u8], mut reader: R) -> Result<(), String> {
         use flate2::write::ZlibEncoder;
         use flate2::Compression;
+
+        let display_name = decode_name(filename);
         let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
-        encoder.write_all(&table_data)
-            .map_err(|e| format!("Failed to compress table: {}", e))?;
-        let compressed_table = encoder.finish()
-            .map_err(|e| format!("Failed to finish compression: {}", e))?;
-
-        // Write compressed table
-        let table_offset = data_offset;
-        file.write_all(&(compressed_table.len() as i32).to_le_bytes())
-            .map_err(|e| format!("Failed to write table size: {}", e))?;
-        file.write_all(&(table_data.len() as i32).to_le_bytes())
-            .map_err(|e| format!("Failed to write real table size: {}", e))?;
-        file.write_all(&compressed_table)
-            .map_err(|e| format!("Failed to write compressed table: {}", e))?;
-
-        // Update header with new table offset and file count
-        file.seek(SeekFrom::Start(30))
-            .map_err(|e| format!("Failed to seek to header: {}", e))?;
-        
-        let new_table_offset = table_offset - GRF_HEADER_SIZE as u64;
-        file.write_all(&new_table_offset.to_le_bytes())
-            .map_err(|e| format!("Failed to write table offset: {}", e))?;
-
-        // Update file count (seed + count + 7 = raw_count)
-        let new_count = table.len() as i32;
-        let seed = 0; // Simplified
-        let raw_count = new_count + seed + 7;
-        
-        file.write_all(&seed.to_le_bytes())
-            .map_err(|e| format!("Failed to write seed: {}", e))?;
-        file.write_all(&raw_count.to_le_bytes())
-            .map_err(|e| format!("Failed to write file count: {}", e))?;
-
-        file.flush()
+        let mut buffer = vec![0u8; 64 * 1024];
+        let mut real_size: u64 = 0;
+        // Absolute offset and length of the data already spilled to the end
+        let mut spilled: Option<(u64, u64)> = None;
+
+        loop {
+            let read = reader.read(&mut buffer)
+                .map_err(|e| format!("Failed to read {}: {}", display_name, e))?;
+            if read == 0 {
+                break;
+            }
+            real_size += read as u64;
+
+            encoder.write_all(&buffer[..read])
+                .map_err(|e| format!("Failed to compress file {}: {}", display_name, e))?;
+
+            if encoder.get_ref().len() >= SPILL_THRESHOLD {
+                let chunk = std::mem::take(encoder.get_mut());
+                let (offset, length) = spilled.get_or_insert((self.free_space.end_of_data(), 0));
+                self.write_at(*offset + *length, &chunk)?;
+                *length += chunk.len() as u64;
+            }
+        }
+
+        let tail = encoder.finish()
+            .map_err(|e| format!("Failed to finish compression for {}: {}", display_name, e))?;
+
+        let compressed_size = spilled.map_or(0, |(_, length)| length) + tail.len() as u64;
+
+        self.place_entry(filename, compressed_size, real_size, GRF_FLAG_FILE, |merge, aligned_size| {
+            match spilled {
+                Some((offset, length)) => {
+                    merge.write_at(offset + length, &tail)?;
+                    Ok(merge.free_space.append(aligned_size))
+                }
+                None => {
+                    let offset = merge.free_space.allocate(aligned_size);
+                    merge.write_at(offset, &tail)?;
+                    Ok(offset)
+                }
+            }
+        })
+    }
+
+    /// Copies an already zlib-compressed stream of `compressed_size` bytes into
+    /// the GRF as is. The caller vouches that it inflates to `real_size` bytes.
+    pub fn add_compressed<R: Read>(
+        &mut self,
+        filename: &[u8],
+        reader: R,
+        compressed_size: u64,
+        real_size: u64,
+    ) -> Result<(), String> {
+        let display_name = decode_name(filename);
+
+        self.place_entry(filename, compressed_size, real_size, GRF_FLAG_FILE, |merge, aligned_size| {
+            let offset = merge.free_space.allocate(aligned_size);
+
+            merge.file.seek(SeekFrom::Start(offset))
+                .map_err(|e| format!("Failed to seek to data offset: {}", e))?;
+            let copied = std::io::copy(&mut reader.take(compressed_size), &mut merge.file)
+                .map_err(|e| format!("Failed to copy {}: {}", display_name, e))?;
+            if copied != compressed_size {
+                return Err(format!("Data of {} is truncated", display_name));
+            }
+
+            Ok(offset)
+        })
+    }
+
+    /// Writes the new file table after the data, points the header at it and
+    /// commits the journal.
+    pub fn finish(mut self) -> Result<(), String> {
+        let data_end = self.free_space.end_of_data();
+        self.file.seek(SeekFrom::Start(data_end))
+            .map_err(|e| format!("Failed to seek to end of data: {}", e))?;
+
+        let table_offset = data_end - GRF_HEADER_SIZE as u64;
+        let table_size = write_file_table(&mut self.file, &self.header, &self.table)?;
+
+        // Data and table must be on disk before the header points at them
+        self.file.sync_data()
             .map_err(|e| format!("Failed to flush file: {}", e))?;
 
+        write_header(&mut self.file, &self.header, table_offset, self.table.len() as i32)?;
+
+        // Drop whatever followed the new table (old table, stale appended data)
+        self.file.set_len(data_end + table_size)
+            .map_err(|e| format!("Failed to truncate GRF: {}", e))?;
+
+        self.file.sync_all()
+            .map_err(|e| format!("Failed to flush file: {}", e))?;
+
+        match self.journal.take() {
+            Some(journal) => journal.commit(),
+            None => Ok(()),
+        }
+    }
+
+    /// Lays out a new entry of `compressed_size` bytes. `write_body` is given the
+    /// aligned length to reserve, writes the data and returns its absolute offset;
+    /// the padding and the table entry are filled in here.
+    fn place_entry<W>(
+        &mut self,
+        filename: &[u8],
+        compressed_size: u64,
+        real_size: u64,
+        flags: u8,
+        write_body: W,
+    ) -> Result<(), String>
+    where
+        W: FnOnce(&mut Self, u64) -> Result<u64, String>,
+    {
+        let display_name = decode_name(filename);
+
+        // Checked before the body is written, the table only holds 32-bit sizes
+        let too_large = || format!("{} is too large for a GRF", display_name);
+        let aligned_size = compressed_size.checked_add(7).ok_or_else(too_large)? & !7;
+        let entry_compressed_size = i32::try_from(compressed_size).map_err(|_| too_large())?;
+        let entry_aligned_size = i32::try_from(aligned_size).map_err(|_| too_large())?;
+        let entry_real_size = i32::try_from(real_size).map_err(|_| too_large())?;
+
+        let data_offset = write_body(self, aligned_size)?;
+
+        let padding = vec![0u8; (aligned_size - compressed_size) as usize];
+        self.write_at(data_offset + compressed_size, &padding)?;
+
+        self.insert_entry(GrfEntry {
+            filename: filename.iter().map(|&byte| if byte == b'/' { b'\\' } else { byte }).collect(),
+            compressed_size: entry_compressed_size,
+            compressed_size_aligned: entry_aligned_size,
+            real_size: entry_real_size,
+            flags,
+            offset: data_offset - GRF_HEADER_SIZE as u64,
+        });
+
         Ok(())
     }
+
+    fn insert_entry(&mut self, entry: GrfEntry) {
+        self.table.insert(grf_key(&entry.filename), entry);
+    }
+
+    fn write_at(&mut self, offset: u64, data: &[u8]) -> Result<(), String> {
+        self.file.seek(SeekFrom::Start(offset))
+            .map_err(|e| format!("Failed to seek to data offset: {}", e))?;
+        self.file.write_all(data)
+            .map_err(|e| format!("Failed to write file data: {}", e))
+    }
+}
+
+impl Drop for GrfMerge {
+    fn drop(&mut self) {
+        // Not finished: put the old header and table back right away
+        if self.journal.take().is_some() {
+            if let Err(e) = journal::recover(&self.grf_path) {
+                eprintln!("Failed to roll back {}: {}", self.grf_path, e);
+            }
+        }
+    }
+}
+
+/// Copies every live entry from `source` into `target` in offset order and
+/// writes the new table and header.
+fn write_repacked<F>(
+    source: &mut File,
+    target: &mut File,
+    header: &GrfHeader,
+    table: &mut HashMap<Vec<u8>, GrfEntry>,
+    grf_name: &str,
+    on_progress: &mut F,
+) -> Result<(), String>
+where
+    F: FnMut(RepackProgress),
+{
+    // Header is written last, once the table offset is known
+    target.write_all(&[0u8; GRF_HEADER_SIZE])
+        .map_err(|e| format!("Failed to write header placeholder: {}", e))?;
+
+    let mut keys: Vec<Vec<u8>> = table.keys().cloned().collect();
+    keys.sort_by_key(|key| table[key].offset);
+
+    let total = keys.len() as u32;
+    let mut data_end = GRF_HEADER_SIZE as u64;
+    // Entries sharing the same data keep sharing it after the repack
+    let mut moved: HashMap<(u64, i32), u64> = HashMap::new();
+
+    for (index, key) in keys.iter().enumerate() {
+        let entry = table.get_mut(key).unwrap();
+        let extent = (entry.offset, entry.compressed_size_aligned);
+
+        if !entry.is_file() {
+            // Directory placeholders own no data, whatever offset they claim
+            entry.offset = 0;
+            entry.compressed_size = 0;
+            entry.compressed_size_aligned = 0;
+            entry.real_size = 0;
+        } else if let Some(&new_offset) = moved.get(&extent) {
+            entry.offset = new_offset;
+        } else {
+            source.seek(SeekFrom::Start(GRF_HEADER_SIZE as u64 + entry.offset))
+                .map_err(|e| format!("Failed to seek to {}: {}", entry.display_name(), e))?;
+
+            let length = entry.compressed_size_aligned as u64;
+            let copied = std::io::copy(&mut (&mut *source).take(length), target)
+                .map_err(|e| format!("Failed to copy {}: {}", entry.display_name(), e))?;
+            if copied != length {
+                return Err(format!("Data of {} is truncated", entry.display_name()));
+            }
+
+            let new_offset = data_end - GRF_HEADER_SIZE as u64;
+            moved.insert(extent, new_offset);
+            entry.offset = new_offset;
+            data_end += length;
+        }
+
+        let current = index as u32 + 1;
+        on_progress(RepackProgress {
+            grf: grf_name.to_string(),
+            current,
+            total,
+            percentage: (current as f64 / total as f64) * 100.0,
+        });
+    }
+
+    let table_offset = data_end - GRF_HEADER_SIZE as u64;
+    write_file_table(target, header, table)?;
+    write_header(target, header, table_offset, table.len() as i32)?;
+
+    target.sync_all()
+        .map_err(|e| format!("Failed to flush temporary GRF: {}", e))
+}
+
+/// Serializes and compresses the file table at the current position.
+/// Entry offsets are 32-bit for v1.x/2.0 and 64-bit for v3.0.
+/// Returns the number of bytes written.
+fn write_file_table(
+    file: &mut File,
+    header: &GrfHeader,
+    table: &HashMap<Vec<u8>, GrfEntry>,
+) -> Result<u64, String> {
+    let mut table_data = Vec::new();
+    for entry in table.values() {
+        // Write filename (null-terminated)
+        table_data.extend_from_slice(&entry.filename);
+        table_data.push(0);
+
+        // Write entry fields
+        table_data.extend_from_slice(&entry.compressed_size.to_le_bytes());
+        table_data.extend_from_slice(&entry.compressed_size_aligned.to_le_bytes());
+        table_data.extend_from_slice(&entry.real_size.to_le_bytes());
+        table_data.push(entry.flags);
+
+        if header.is_v3() {
+            table_data.extend_from_slice(&entry.offset.to_le_bytes());
+        } else {
+            let offset = u32::try_from(entry.offset)
+                .map_err(|_| format!("Offset of {} does not fit a 0x{:X} GRF", entry.display_name(), header.version))?;
+            table_data.extend_from_slice(&offset.to_le_bytes());
+        }
+    }
+
+    // Compress table
+    use flate2::write::ZlibEncoder;
+    use flate2::Compression;
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&table_data)
+        .map_err(|e| format!("Failed to compress table: {}", e))?;
+    let compressed_table = encoder.finish()
+        .map_err(|e| format!("Failed to finish compression: {}", e))?;
+
+    // v3.0 tables start with 4 extra bytes before the sizes
+    let mut written = 0;
+    if header.is_v3() {
+        file.write_all(&[0u8; GRF_V3_TABLE_PREFIX_SIZE])
+            .map_err(|e| format!("Failed to write table prefix: {}", e))?;
+        written += GRF_V3_TABLE_PREFIX_SIZE as u64;
+    }
+
+    file.write_all(&(compressed_table.len() as i32).to_le_bytes())
+        .map_err(|e| format!("Failed to write table size: {}", e))?;
+    file.write_all(&(table_data.len() as i32).to_le_bytes())
+        .map_err(|e| format!("Failed to write real table size: {}", e))?;
+    file.write_all(&compressed_table)
+        .map_err(|e| format!("Failed to write compressed table: {}", e))?;
+    written += 8 + compressed_table.len() as u64;
+
+    Ok(written)
+}
+
+/// Rewrites the 46-byte header, keeping the archive's original key, seed and version.
+fn write_header(
+    file: &mut File,
+    header: &GrfHeader,
+    table_offset: u64,
+    file_count: i32,
+) -> Result<(), String> {
+    let mut buffer = Vec::with_capacity(GRF_HEADER_SIZE);
+
+    // Signature (16 bytes, null-padded)
+    buffer.extend_from_slice(GRF_SIGNATURE.as_bytes());
+    buffer.push(0);
+
+    // Key (14 bytes)
+    let mut key = [0u8; 14];
+    let key_len = header.key.len().min(key.len());
+    key[..key_len].copy_from_slice(&header.key[..key_len]);
+    buffer.extend_from_slice(&key);
+
+    if header.is_v3() {
+        // v3.0: 8-byte offset followed by the real file count
+        buffer.extend_from_slice(&table_offset.to_le_bytes());
+        buffer.extend_from_slice(&file_count.to_le_bytes());
+    } else {
+        // v1.x / v2.0: 4-byte offset, seed and raw count (real + seed + 7)
+        let offset = u32::try_from(table_offset)
+            .map_err(|_| format!("File table offset does not fit a 0x{:X} GRF", header.version))?;
+        buffer.extend_from_slice(&offset.to_le_bytes());
+        buffer.extend_from_slice(&header.seed.to_le_bytes());
+        buffer.extend_from_slice(&(file_count + header.seed + 7).to_le_bytes());
+    }
+
+    buffer.extend_from_slice(&header.version.to_le_bytes());
+
+    file.seek(SeekFrom::Start(0))
+        .map_err(|e| format!("Failed to seek to header: {}", e))?;
+    file.write_all(&buffer)
+        .map_err(|e| format!("Failed to write header: {}", e))?;
+
+    Ok(())
 }
@@ -0,0 +1,52 @@
+use std::io::Read;
+
+/// Name of the checksum manifest Thor Patcher can embed in a patch
+pub const INTEGRITY_FILE_NAME: &[u8] = b"data.integrity";
+
+/// `data.integrity`: one `path=CRC32` line per file (hex, optionally `0x`
+/// prefixed), with paths as raw CP949 bytes. `[section]` headers and `;`/`#`
+/// comments are ignored.
+#[derive(Debug, Clone, Default)]
+pub struct IntegrityManifest {
+    pub entries: Vec<(Vec<u8>, u32)>,
+}
+
+impl IntegrityManifest {
+    pub fn parse(content: &[u8]) -> Result<Self, String> {
+        let mut entries = Vec::new();
+
+        for line in content.split(|&byte| byte == b'\n') {
+            let line = line.trim_ascii();
+            if line.is_empty() || line.starts_with(b"[") || line.starts_with(b";") || line.starts_with(b"#") {
+                continue;
+            }
+
+            let Some(separator) = line.iter().rposition(|&byte| byte == b'=') else {
+                return Err(format!("Invalid data.integrity line: {}", String::from_utf8_lossy(line)));
+            };
+            let (path, checksum) = (line[..separator].trim_ascii(), line[separator + 1..].trim_ascii());
+
+            let checksum = String::from_utf8_lossy(checksum);
+            let digits = checksum.strip_prefix("0x").or_else(|| checksum.strip_prefix("0X")).unwrap_or(&checksum);
+            let crc = u32::from_str_radix(digits, 16)
+                .map_err(|_| format!("Invalid checksum in data.integrity: {}", checksum))?;
+
+            entries.push((path.to_vec(), crc));
+        }
+
+        Ok(Self { entries })
+    }
+}
+
+pub fn crc32<R: Read>(mut reader: R) -> std::io::Result<u32> {
+    let mut hasher = crc32fast::Hasher::new();
+    let mut buffer = [0u8; 64 * 1024];
+
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            return Ok(hasher.finalize());
+        }
+        hasher.update(&buffer[..read]);
+    }
+}
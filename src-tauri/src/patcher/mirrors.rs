@@ -0,0 +1,132 @@
+use super::downloader::{download_with_retry, DownloadProgress, ExpectedFile};
+use super::patch_list::{fetch_patch_list, PatchInfo};
+use crate::config::PatchServer;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Attempts per mirror before falling over to the next one
+const DOWNLOAD_ATTEMPTS: u32 = 3;
+
+/// The mirror used last time stays first unless another one answers this many
+/// times faster, so partial downloads remain resumable and the choice is stable.
+const SWITCH_RATIO: f64 = 1.5;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MirrorRanking {
+    pub name: String,
+    /// Round trip of the probe, None when the mirror did not answer
+    pub latency_ms: Option<u64>,
+    pub error: Option<String>,
+}
+
+async fn probe(client: &reqwest::Client, server: &PatchServer) -> MirrorRanking {
+    let started = Instant::now();
+    let response = client
+        .head(&server.plist_url)
+        .timeout(PROBE_TIMEOUT)
+        .send()
+        .await;
+
+    // Any answer short of a server error means the mirror is up
+    let (latency_ms, error) = match response {
+        Ok(response) if !response.status().is_server_error() => {
+            (Some(started.elapsed().as_millis() as u64), None)
+        }
+        Ok(response) => (None, Some(format!("HTTP {}", response.status()))),
+        Err(e) => (None, Some(e.to_string())),
+    };
+
+    MirrorRanking {
+        name: server.name.clone(),
+        latency_ms,
+        error,
+    }
+}
+
+/// Probes all mirrors concurrently and returns them fastest first, the ones
+/// that did not answer last (in config order).
+pub async fn rank_mirrors(servers: &[PatchServer], last_used: Option<&str>) -> Vec<MirrorRanking> {
+    let client = reqwest::Client::new();
+    let mut ranking = futures_util::future::join_all(
+        servers.iter().map(|server| probe(&client, server)),
+    ).await;
+
+    ranking.sort_by_key(|mirror| mirror.latency_ms.unwrap_or(u64::MAX));
+
+    let fastest = ranking.first().and_then(|mirror| mirror.latency_ms);
+    let last_used = last_used.and_then(|name| ranking.iter().position(|mirror| mirror.name == name));
+
+    if let (Some(fastest), Some(position)) = (fastest, last_used) {
+        if ranking[position].latency_ms.is_some_and(|latency| latency as f64 <= fastest as f64 * SWITCH_RATIO) {
+            let mirror = ranking.remove(position);
+            ranking.insert(0, mirror);
+        }
+    }
+
+    ranking
+}
+
+/// Patch servers in the order they are tried for every file. The patch lists
+/// of fallback mirrors are fetched once, the first time one is needed.
+pub struct MirrorSet {
+    servers: Vec<PatchServer>,
+    lists: tokio::sync::Mutex<HashMap<String, Option<Vec<PatchInfo>>>>,
+}
+
+impl MirrorSet {
+    pub fn new(servers: Vec<PatchServer>) -> Self {
+        Self {
+            servers,
+            lists: tokio::sync::Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Downloads a patch, retrying each mirror before moving on to the next one
+    /// that publishes the very same file.
+    pub async fn download<F>(
+        &self,
+        patch: &PatchInfo,
+        dest_path: &str,
+        expected: &ExpectedFile,
+        on_progress: F,
+    ) -> Result<(), String>
+    where
+        F: FnMut(DownloadProgress) + Clone,
+    {
+        let mut result = Err("No patch servers configured".to_string());
+
+        for (index, server) in self.servers.iter().enumerate() {
+            if index > 0 && !self.provides(server, patch).await {
+                println!("Skipping {}: it does not provide {}", server.name, patch.filename);
+                continue;
+            }
+
+            let patch_url = format!("{}/{}", server.patch_url, patch.filename);
+            result = download_with_retry(&patch_url, dest_path, expected, on_progress.clone(), DOWNLOAD_ATTEMPTS).await;
+
+            match &result {
+                Ok(()) => break,
+                Err(e) => println!("Failed to download {} from {}: {}", patch.filename, server.name, e),
+            }
+        }
+
+        result
+    }
+
+    async fn provides(&self, server: &PatchServer, patch: &PatchInfo) -> bool {
+        // Held while fetching so concurrent downloads do not fetch the same list twice
+        let mut lists = self.lists.lock().await;
+
+        if !lists.contains_key(&server.plist_url) {
+            let list = fetch_patch_list(&server.plist_url).await.ok();
+            lists.insert(server.plist_url.clone(), list);
+        }
+
+        lists[&server.plist_url]
+            .as_ref()
+            .is_some_and(|list| list.iter().any(|other| patch.is_same_file(other)))
+    }
+}
@@ -1,4 +1,8 @@
+pub mod data_ini;
 pub mod downloader;
+pub mod integrity;
+pub mod mirrors;
 pub mod patch_list;
+pub mod safe_path;
 pub mod thor_patcher;
 pub mod grf;
@@ -11,6 +11,24 @@ pub struct PatchInfo {
     pub size: Option<u64>,
 }
 
+impl PatchInfo {
+    /// Whether a mirror's entry refers to the same file: same name and, when
+    /// the patch list gives them, the same hash and size.
+    pub fn is_same_file(&self, other: &PatchInfo) -> bool {
+        let same_hash = match (&self.hash, &other.hash) {
+            (Some(hash), Some(other_hash)) => hash.trim().eq_ignore_ascii_case(other_hash.trim()),
+            (Some(_), None) => false,
+            (None, _) => true,
+        };
+        let same_size = match (self.size, other.size) {
+            (Some(size), Some(other_size)) => size == other_size,
+            _ => true,
+        };
+
+        self.filename.eq_ignore_ascii_case(&other.filename) && same_hash && same_size
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LocalCache {
     #[serde(rename = "lastPatchId")]
@@ -21,6 +39,9 @@ pub struct LocalCache {
     pub grf_versions: HashMap<String, String>,
     #[serde(rename = "lastCheck")]
     pub last_check: String,
+    /// Mirror the last update was fetched from
+    #[serde(rename = "patchServer", default)]
+    pub patch_server: Option<String>,
 }
 
 impl Default for LocalCache {
@@ -30,6 +51,7 @@ impl Default for LocalCache {
             installed_patches: Vec::new(),
             grf_versions: HashMap::new(),
             last_check: chrono::Utc::now().to_rfc3339(),
+            patch_server: None,
         }
     }
 }
@@ -0,0 +1,98 @@
+use std::path::{Path, PathBuf};
+
+/// Names Windows maps to devices in every directory, with or without an extension
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL",
+    "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+    "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Joins a path taken from a patch (archive entry, THOR target GRF, ...) onto
+/// `base`, refusing anything that could resolve outside of it: `..`
+/// components, absolute and UNC paths, drive prefixes and device names.
+/// Both `/` and `\` are treated as separators, as the client does.
+pub fn safe_join(base: &Path, name: &str) -> Result<PathBuf, String> {
+    let unsafe_path = |reason: &str| format!("Refusing to write patch entry {:?}: {}", name, reason);
+
+    if name.starts_with(['/', '\\']) {
+        return Err(unsafe_path("absolute path"));
+    }
+
+    let mut path = base.to_path_buf();
+    let mut components = 0;
+
+    for component in name.split(['/', '\\']) {
+        if component.is_empty() || component == "." {
+            continue;
+        }
+
+        if component.contains(':') {
+            return Err(unsafe_path("drive prefix or stream name"));
+        }
+        if component.chars().any(|c| c.is_control()) {
+            return Err(unsafe_path("control character in name"));
+        }
+
+        // Windows drops trailing dots and spaces, so ".. " would still be ".."
+        let trimmed = component.trim_end_matches(['.', ' ']);
+        if trimmed.is_empty() {
+            return Err(unsafe_path("parent directory reference"));
+        }
+
+        let stem = trimmed.split('.').next().unwrap_or(trimmed).trim_end();
+        if RESERVED_NAMES.iter().any(|reserved| stem.eq_ignore_ascii_case(reserved)) {
+            return Err(unsafe_path("reserved device name"));
+        }
+
+        path.push(component);
+        components += 1;
+    }
+
+    if components == 0 {
+        return Err(unsafe_path("empty path"));
+    }
+
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_paths_leaving_the_base() {
+        let base = Path::new("client");
+        let unsafe_names = [
+            "..\\..\\x",
+            "a/../../x",
+            "C:/x",
+            "\\\\server\\share",
+            "/etc/passwd",
+            "\\x",
+            "CON",
+            "data/aux.txt",
+            "data/lpt1 .txt",
+            ".. /x",
+            "data/... /x",
+            "data/a.txt:stream",
+            "data/a\u{0}.txt",
+            "",
+            "./.",
+        ];
+
+        for name in unsafe_names {
+            assert!(safe_join(base, name).is_err(), "{:?} was accepted", name);
+        }
+    }
+
+    #[test]
+    fn joins_regular_paths() {
+        let base = Path::new("client");
+        let expected: PathBuf = ["client", "data", "sprite", "a.spr"].iter().collect();
+
+        assert_eq!(safe_join(base, "data\\sprite\\a.spr").unwrap(), expected);
+        assert_eq!(safe_join(base, "data/sprite/a.spr").unwrap(), expected);
+        assert_eq!(safe_join(base, "./data//sprite\\a.spr").unwrap(), expected);
+        assert_eq!(safe_join(base, "..data/a").unwrap(), base.join("..data").join("a"));
+    }
+}
@@ -1,23 +1,74 @@
-use super::grf::{GrfReader, GrfWriter};
-use std::collections::{HashMap, HashSet};
+use super::data_ini;
+use super::grf::types::{decode_name, encode_name, GRF_FLAG_REMOVE, GRF_HEADER_SIZE, GRF_SIGNATURE};
+use super::grf::{journal, GrfMerge, GrfReader, GrfWriter};
+use super::integrity::{crc32, IntegrityManifest, INTEGRITY_FILE_NAME};
+use super::patch_list::PatchInfo;
+use super::safe_path::safe_join;
+use crate::config::PatchingConfig;
+use flate2::read::ZlibDecoder;
 use std::fs;
-use std::path::Path;
+use std::io::Read;
+use std::path::{Path, PathBuf};
 use zip::ZipArchive;
 
+/// Suffix of the previous launcher executable after it replaced itself
+const REPLACED_EXE_SUFFIX: &str = ".old";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PatchOutcome {
+    Applied,
+    /// The patch replaced the running launcher, which must restart to use it
+    RestartRequired,
+}
+
+/// Per-patch settings taken from the patch list.
+#[derive(Debug, Clone)]
+pub struct PatchOptions {
+    /// GRF that receives data/ files (plist `target=`, else the client default)
+    pub target_grf: String,
+    /// Write every file to disk even when the target GRF exists (plist `extract=true`)
+    pub force_extract: bool,
+}
+
+impl PatchOptions {
+    pub fn from_patch(patch: &PatchInfo, default_grf_name: &str) -> Self {
+        Self {
+            target_grf: patch.target_grf.clone().unwrap_or_else(|| default_grf_name.to_string()),
+            force_extract: patch.force_extract,
+        }
+    }
+}
+
 pub fn extract_thor_patch(
     thor_path: &str,
     target_dir: &str,
-    default_grf_name: &str,
-) -> Result<(), String> {
+    options: &PatchOptions,
+    patching: &PatchingConfig,
+) -> Result<PatchOutcome, String> {
+    let default_grf_name = options.target_grf.as_str();
+
     // Check for "ASSF" signature (legacy format)
     let mut file = fs::File::open(thor_path)
         .map_err(|e| format!("Failed to open THOR file: {}", e))?;
 
     let mut signature = [0u8; 4];
-    use std::io::{Read, Seek};
+    use std::io::Seek;
     if file.read_exact(&mut signature).is_ok() && &signature == b"ASSF" {
         // Legacy THOR format
-        return extract_legacy_thor(thor_path, target_dir, default_grf_name);
+        return extract_legacy_thor(thor_path, target_dir, options, patching);
+    }
+
+    // GZIP magic: RGZ patch
+    if signature[..2] == [0x1F, 0x8B] {
+        return extract_rgz(thor_path, target_dir, options, patching);
+    }
+
+    // "Master of Magic": GPF patch, a GRF merged into the target GRF
+    file.seek(std::io::SeekFrom::Start(0))
+        .map_err(|e| format!("Failed to seek file: {}", e))?;
+    let mut grf_signature = [0u8; 15];
+    if file.read_exact(&mut grf_signature).is_ok() && grf_signature == GRF_SIGNATURE.as_bytes() {
+        return extract_gpf(thor_path, target_dir, options, patching);
     }
 
     // Reset cursor for ZIP check
@@ -27,94 +78,248 @@ pub fn extract_thor_patch(
     let mut archive = ZipArchive::new(file)
         .map_err(|e| format!("Failed to read THOR archive (not ZIP or legacy): {}", e))?;
 
-    // Separate files by destination
-    let mut grf_files: HashMap<String, Vec<u8>> = HashMap::new();
-    let mut disk_files: Vec<(String, Vec<u8>)> = Vec::new();
-
-    let grf_path = Path::new(target_dir).join(default_grf_name);
-    let use_grf = grf_path.exists();
+    let mut target = PatchTarget::new(target_dir, default_grf_name, patching, !options.force_extract)?;
 
     for i in 0..archive.len() {
-        let mut file = archive.by_index(i)
+        let file = archive.by_index(i)
             .map_err(|e| format!("Failed to read archive entry: {}", e))?;
 
         if file.is_dir() {
             continue;
         }
 
-        let entry_name = file.name().to_string();
-        let normalized = entry_name.replace('\\', "/");
+        // GRF names are kept as CP949 bytes, only disk paths are decoded
+        let raw_name = encode_name(file.name_raw());
+        target.add_file(&raw_name, file)?;
+    }
+
+    target.finish()?;
+    Ok(PatchOutcome::Applied)
+}
 
-        let mut data = Vec::new();
-        std::io::Read::read_to_end(&mut file, &mut data)
-            .map_err(|e| format!("Failed to read file data: {}", e))?;
+/// Routes patch entries one at a time: data/ files are merged into the target
+/// GRF (opened on first use), everything else is written to disk.
+struct PatchTarget<'a> {
+    target_dir: &'a str,
+    grf_name: &'a str,
+    grf_path: PathBuf,
+    patching: &'a PatchingConfig,
+    use_grf: bool,
+    merge: Option<GrfMerge>,
+    grf_files: usize,
+    grf_deletions: usize,
+    /// Checksums from the patch's data.integrity, checked once it is applied
+    integrity: Option<IntegrityManifest>,
+}
 
-        // Files in data/ go to GRF if it exists, otherwise to disk
-        if use_grf && (normalized.starts_with("data/") || normalized.starts_with("data\\")) {
-            grf_files.insert(normalized, data);
-        } else {
-            disk_files.push((entry_name, data));
-        }
+impl<'a> PatchTarget<'a> {
+    fn new(target_dir: &'a str, grf_name: &'a str, patching: &'a PatchingConfig, merge_enabled: bool) -> Result<Self, String> {
+        // The GRF name may come from the patch itself (THOR header, plist target=)
+        let grf_path = safe_join(Path::new(target_dir), grf_name)?;
+
+        Ok(Self {
+            target_dir,
+            grf_name,
+            // Files in data/ go to GRF if it exists (or may be created), otherwise to disk
+            use_grf: merge_enabled && (grf_path.exists() || patching.create_grf),
+            grf_path,
+            patching,
+            merge: None,
+            grf_files: 0,
+            grf_deletions: 0,
+            integrity: None,
+        })
     }
 
-    apply_patch_files(target_dir, default_grf_name, grf_files, disk_files)
-}
+    fn add_file<R: Read>(&mut self, name: &[u8], mut reader: R) -> Result<(), String> {
+        let normalized = normalize_name(name);
+
+        // The manifest describes the patch, it is not a client file
+        if normalized.eq_ignore_ascii_case(INTEGRITY_FILE_NAME) {
+            let mut content = Vec::new();
+            reader.read_to_end(&mut content)
+                .map_err(|e| format!("Failed to read data.integrity: {}", e))?;
+            self.integrity = Some(IntegrityManifest::parse(&content)?);
+            return Ok(());
+        }
+
+        if self.use_grf && normalized.starts_with(b"data/") {
+            self.grf()?.add_file(&normalized, reader)?;
+            self.grf_files += 1;
+            return Ok(());
+        }
+
+        let entry_name = decode_name(&normalized);
+        let target_path = safe_join(Path::new(self.target_dir), &entry_name)?;
 
-fn apply_patch_files(
-    target_dir: &str, 
-    default_grf_name: &str, 
-    grf_files: HashMap<String, Vec<u8>>, 
-    disk_files: Vec<(String, Vec<u8>)>
-) -> Result<(), String> {
-    // Extract disk files
-    for (entry_name, data) in disk_files {
-        let target_path = Path::new(target_dir).join(&entry_name);
-        
         if let Some(parent) = target_path.parent() {
             fs::create_dir_all(parent)
                 .map_err(|e| format!("Failed to create directory: {}", e))?;
         }
 
-        fs::write(&target_path, data)
+        let mut output = fs::File::create(&target_path)
             .map_err(|e| format!("Failed to write file: {}", e))?;
+        std::io::copy(&mut reader, &mut output)
+            .map_err(|e| format!("Failed to write file {}: {}", entry_name, e))?;
+
+        Ok(())
+    }
+
+    fn add_directory(&mut self, name: &[u8]) -> Result<(), String> {
+        let entry_name = decode_name(&normalize_name(name));
+        fs::create_dir_all(safe_join(Path::new(self.target_dir), &entry_name)?)
+            .map_err(|e| format!("Failed to create directory {}: {}", entry_name, e))
+    }
+
+    /// Like `add_file` for a zlib stream that is known to inflate to `real_size`
+    /// bytes: GRF entries get the compressed bytes as is, disk files are inflated.
+    fn add_compressed<R: Read>(&mut self, name: &[u8], reader: R, compressed_size: u64, real_size: u64) -> Result<(), String> {
+        let normalized = normalize_name(name);
+
+        if self.use_grf && normalized.starts_with(b"data/") {
+            self.grf()?.add_compressed(&normalized, reader, compressed_size, real_size)?;
+            self.grf_files += 1;
+            return Ok(());
+        }
+
+        self.add_file(name, ZlibDecoder::new(reader.take(compressed_size)))
+    }
+
+    fn remove_file(&mut self, name: &[u8]) -> Result<(), String> {
+        let normalized = normalize_name(name);
+
+        if self.use_grf && normalized.starts_with(b"data/") {
+            self.grf()?.remove_file(&normalized);
+            self.grf_deletions += 1;
+            return Ok(());
+        }
+
+        let entry_name = decode_name(&normalized);
+        let target_path = safe_join(Path::new(self.target_dir), &entry_name)?;
+
+        if target_path.is_file() {
+            fs::remove_file(&target_path)
+                .map_err(|e| format!("Failed to delete file {}: {}", entry_name, e))?;
+        }
+
+        Ok(())
     }
 
-    // Patch GRF files
-    if !grf_files.is_empty() {
-        let grf_path = Path::new(target_dir).join(default_grf_name);
-        println!("Patching {} files into {}...", grf_files.len(), default_grf_name);
+    fn grf(&mut self) -> Result<&mut GrfMerge, String> {
+        if self.merge.is_none() {
+            let merge = self.open_grf()?;
+            self.merge = Some(merge);
+        }
+        Ok(self.merge.as_mut().expect("merge was just opened"))
+    }
 
+    fn open_grf(&self) -> Result<GrfMerge, String> {
+        let grf_path = &self.grf_path;
         let reader = GrfReader::new(grf_path.to_string_lossy().to_string());
         let writer = GrfWriter::new();
 
+        // Patches may introduce new GRFs (e.g. target=event.grf)
+        if !grf_path.exists() {
+            println!("Creating {}...", self.grf_name);
+            writer.create(&grf_path.to_string_lossy(), self.patching.new_grf_version)?;
+
+            if self.patching.register_new_grf {
+                data_ini::register_grf(self.target_dir, self.grf_name, self.patching.register_new_grf_first)?;
+            }
+        }
+
+        // Finish rolling back an interrupted merge before reading the table
+        journal::recover(&grf_path.to_string_lossy())?;
+
         let header = reader.read_header()
             .map_err(|e| format!("Failed to read GRF header: {}", e))?;
         
         let table = reader.read_file_table(&header)
             .map_err(|e| format!("Failed to read GRF table: {}", e))?;
 
-        writer.quick_merge(
-            &grf_path.to_string_lossy(),
-            &header,
-            table,
-            grf_files,
-            HashSet::new(),
-        )?;
+        writer.begin_merge(&grf_path.to_string_lossy(), &header, table)
     }
 
-    Ok(())
+    /// Writes the GRF table; until then the merge is rolled back on error.
+    fn finish(mut self) -> Result<(), String> {
+        if let Some(merge) = self.merge.take() {
+            println!(
+                "Patched {} files and removed {} from {}",
+                self.grf_files,
+                self.grf_deletions,
+                self.grf_name
+            );
+            merge.finish()?;
+        }
+
+        if let Some(manifest) = &self.integrity {
+            if self.patching.check_integrity {
+                self.verify_integrity(manifest)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Compares the applied files (in the GRF or on disk, following the same
+    /// routing as `add_file`) with the CRC32 listed in data.integrity.
+    fn verify_integrity(&self, manifest: &IntegrityManifest) -> Result<(), String> {
+        let mut grf_reader: Option<GrfReader> = None;
+        let mut failures = Vec::new();
+
+        for (name, expected) in &manifest.entries {
+            let normalized = normalize_name(name);
+
+            let actual = if self.use_grf && normalized.starts_with(b"data/") {
+                let reader = grf_reader
+                    .get_or_insert_with(|| GrfReader::new(self.grf_path.to_string_lossy().to_string()));
+                reader.get_entry(&normalized)
+                    .and_then(|entry| reader.open_entry(entry))
+                    .and_then(|entry| crc32(entry).map_err(|e| e.to_string()))
+            } else {
+                let target_path = safe_join(Path::new(self.target_dir), &decode_name(&normalized))?;
+                fs::File::open(target_path)
+                    .and_then(crc32)
+                    .map_err(|e| e.to_string())
+            };
+
+            match actual {
+                Ok(crc) if crc == *expected => {}
+                Ok(crc) => failures.push(format!(
+                    "{} (expected {:08X}, got {:08X})",
+                    decode_name(name),
+                    expected,
+                    crc
+                )),
+                Err(e) => failures.push(format!("{} ({})", decode_name(name), e)),
+            }
+        }
+
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(format!(
+                "Integrity check failed for {} file(s): {}",
+                failures.len(),
+                failures.join(", ")
+            ))
+        }
+    }
 }
 
 fn extract_legacy_thor(
     thor_path: &str,
     target_dir: &str,
-    default_grf_name: &str,
-) -> Result<(), String> {
+    options: &PatchOptions,
+    patching: &PatchingConfig,
+) -> Result<PatchOutcome, String> {
+    let default_grf_name = options.target_grf.as_str();
+
     let mut file = fs::File::open(thor_path)
         .map_err(|e| format!("Failed to open THOR file: {}", e))?;
 
     let mut header = vec![0u8; 35]; // Read minimal header size
-    use std::io::{Read, Seek};
+    use std::io::Seek;
     file.read_exact(&mut header)
         .map_err(|e| format!("Failed to read legacy header: {}", e))?;
 
@@ -129,29 +334,47 @@ fn extract_legacy_thor(
     // Parse Mode (at 0x1D)
     let mode = u16::from_le_bytes([header[0x1D], header[0x1E]]);
     
-    let mut file_table_offset: u32 = 0;
-    let mut _file_table_comp_len: u32 = 0;
-    let mut target_grf_file = String::new();
+    // 0 = extract everything to disk, 1 = merge data/ files into the target GRF
+    let use_grf_merging = header[0x18] != 0;
+
+    let file_table_offset: u32;
+    let _file_table_comp_len: u32;
+    let target_grf_file: String;
 
     if mode == 0x30 {
         let target_grf_len = header[0x1F] as usize;
         let mut name_buffer = vec![0u8; target_grf_len];
         
-        file.seek(std::io::SeekFrom::Start(0x20)).unwrap();
-        file.read_exact(&mut name_buffer).unwrap();
-        target_grf_file = String::from_utf8_lossy(&name_buffer).to_string();
+        file.seek(std::io::SeekFrom::Start(0x20))
+            .map_err(|e| format!("Failed to seek to target GRF name: {}", e))?;
+        file.read_exact(&mut name_buffer)
+            .map_err(|e| format!("Failed to read target GRF name: {}", e))?;
+        target_grf_file = decode_name(&name_buffer);
 
         let mut table_info = [0u8; 8];
-        file.read_exact(&mut table_info).unwrap();
+        file.read_exact(&mut table_info)
+            .map_err(|e| format!("Failed to read table info: {}", e))?;
         
         _file_table_comp_len = u32::from_le_bytes([table_info[0], table_info[1], table_info[2], table_info[3]]);
         file_table_offset = u32::from_le_bytes([table_info[4], table_info[5], table_info[6], table_info[7]]);
+    } else if mode == 0x21 {
+        // Single file (EXE update): the entry follows the target name and a padding byte
+        let entry_header = 0x20 + header[0x1F] as u64 + 1;
+        file.seek(std::io::SeekFrom::Start(entry_header))
+            .map_err(|e| format!("Failed to seek to entry: {}", e))?;
+
+        return extract_single_file(&mut file, target_dir);
     } else {
-        // Mode 0x21 (EXE update) or others not fully supported in this snippet
-        // Assuming 0x30 for standard patching
         return Err(format!("Unsupported THOR mode: 0x{:X}", mode));
     }
 
+    // The GRF named by the THOR wins over the plist target= / config default
+    let grf_name = if target_grf_file.trim().is_empty() {
+        default_grf_name
+    } else {
+        target_grf_file.trim()
+    };
+
     // Read Compressed File Table
     file.seek(std::io::SeekFrom::Start(file_table_offset as u64))
         .map_err(|e| format!("Failed to seek to file table: {}", e))?;
@@ -164,7 +387,6 @@ fn extract_legacy_thor(
         .map_err(|e| format!("Failed to read compressed table: {}", e))?;
 
     // Decompress Table
-    use flate2::read::ZlibDecoder;
     let mut decoder = ZlibDecoder::new(&compressed_table[..]);
     let mut table_data = Vec::new();
     decoder.read_to_end(&mut table_data)
@@ -172,10 +394,7 @@ fn extract_legacy_thor(
 
     // Parse Entries
     let mut pos = 0;
-    let mut grf_files: HashMap<String, Vec<u8>> = HashMap::new();
-    let mut disk_files: Vec<(String, Vec<u8>)> = Vec::new();
-    let grf_path = Path::new(target_dir).join(default_grf_name);
-    let use_grf = grf_path.exists();
+    let mut target = PatchTarget::new(target_dir, grf_name, patching, use_grf_merging && !options.force_extract)?;
 
     while pos < table_data.len() {
         if pos + 1 > table_data.len() { break; }
@@ -184,7 +403,7 @@ fn extract_legacy_thor(
         pos += 1;
         
         if pos + name_len > table_data.len() { break; }
-        let name = String::from_utf8_lossy(&table_data[pos..pos+name_len]).to_string();
+        let name = table_data[pos..pos+name_len].to_vec();
         pos += name_len;
 
         if pos + 13 > table_data.len() { break; }
@@ -203,37 +422,254 @@ fn extract_legacy_thor(
 
         // Perform Extraction
         if flags == 1 { // File
-             let mut current_pos = file.stream_position().unwrap();
-             file.seek(std::io::SeekFrom::Start(offset as u64)).unwrap();
-             
-             let mut entry_compressed = vec![0u8; size_compressed as usize];
-             file.read_exact(&mut entry_compressed).unwrap();
-             
-             // Decompress entry data
-             // Thor files are typically ZLIB compressed if size_compressed < size_decompressed
-             // If equal, might be stored. But usually assume ZLIB for Thor.
-             let mut entry_decoder = ZlibDecoder::new(&entry_compressed[..]);
-             let mut entry_data = Vec::new();
-             
-             if entry_decoder.read_to_end(&mut entry_data).is_err() {
-                 println!("Warning: Failed to decompress {}, assuming stored", name);
-                 entry_data = entry_compressed;
-             }
-
-             let normalized = name.replace('\\', "/");
-             if use_grf && (normalized.starts_with("data/") || normalized.starts_with("data\\")) {
-                grf_files.insert(normalized, entry_data);
+             // Thor files are typically ZLIB compressed, but may be stored.
+             // Inflate once without keeping the output: a valid stream is then
+             // copied into the GRF as is, a bad one falls back to the raw bytes.
+             file.seek(std::io::SeekFrom::Start(offset as u64))
+                 .map_err(|e| format!("Failed to seek to {}: {}", decode_name(&name), e))?;
+             let mut validator = ZlibDecoder::new((&mut file).take(size_compressed as u64));
+             let inflated_size = std::io::copy(&mut validator, &mut std::io::sink()).ok();
+             // Trailing bytes after the stream would end up inside the GRF entry
+             let consumed_all = validator.total_in() == size_compressed as u64;
+
+             file.seek(std::io::SeekFrom::Start(offset as u64))
+                 .map_err(|e| format!("Failed to seek to {}: {}", decode_name(&name), e))?;
+             let entry_compressed = (&mut file).take(size_compressed as u64);
+
+             if let (Some(real_size), true) = (inflated_size, consumed_all) {
+                 target.add_compressed(&name, entry_compressed, size_compressed as u64, real_size)?;
+             } else if inflated_size.is_some() {
+                 target.add_file(&name, ZlibDecoder::new(entry_compressed))?;
              } else {
-                disk_files.push((name, entry_data));
+                 println!("Warning: Failed to decompress {}, assuming stored", decode_name(&name));
+                 target.add_file(&name, entry_compressed)?;
              }
-
-             file.seek(std::io::SeekFrom::Start(current_pos)).unwrap();
         } else if flags == 5 { // Delete
-             // TODO: Implement deletion logic later
+             target.remove_file(&name)?;
+        }
+    }
+
+    target.finish()?;
+    Ok(PatchOutcome::Applied)
+}
+
+/// RGZ: a GZIP stream of records `type (u8), name length (u8), name`, followed
+/// for 'f' files by `size (u32), data`. 'd' marks a directory, 'e' the end.
+/// Like with the original patchers, RGZ contents are always written to disk.
+fn extract_rgz(
+    rgz_path: &str,
+    target_dir: &str,
+    options: &PatchOptions,
+    patching: &PatchingConfig,
+) -> Result<PatchOutcome, String> {
+    let file = fs::File::open(rgz_path)
+        .map_err(|e| format!("Failed to open RGZ file: {}", e))?;
+    let mut stream = flate2::read::GzDecoder::new(std::io::BufReader::new(file));
+
+    let mut target = PatchTarget::new(target_dir, &options.target_grf, patching, false)?;
+
+    loop {
+        let mut entry_type = [0u8; 1];
+        let read = stream.read(&mut entry_type)
+            .map_err(|e| format!("Failed to read RGZ entry: {}", e))?;
+
+        // Some writers end the stream without an 'e' record
+        if read == 0 || entry_type[0] == b'e' {
+            break;
+        }
+
+        let mut name_length = [0u8; 1];
+        stream.read_exact(&mut name_length)
+            .map_err(|e| format!("Failed to read RGZ entry: {}", e))?;
+
+        let mut name = vec![0u8; name_length[0] as usize];
+        stream.read_exact(&mut name)
+            .map_err(|e| format!("Failed to read RGZ entry name: {}", e))?;
+        // Some writers include the null terminator in the name length
+        if let Some(end) = name.iter().position(|&byte| byte == 0) {
+            name.truncate(end);
+        }
+
+        match entry_type[0] {
+            b'f' => {
+                let mut size = [0u8; 4];
+                stream.read_exact(&mut size)
+                    .map_err(|e| format!("Failed to read size of {}: {}", decode_name(&name), e))?;
+                let size = u32::from_le_bytes(size) as u64;
+
+                let mut data = (&mut stream).take(size);
+                target.add_file(&name, &mut data)?;
+                if data.limit() > 0 {
+                    return Err(format!("Data of {} is truncated", decode_name(&name)));
+                }
+            }
+            b'd' => target.add_directory(&name)?,
+            other => return Err(format!("Unknown RGZ entry type: 0x{:02X}", other)),
+        }
+    }
+
+    target.finish()?;
+    Ok(PatchOutcome::Applied)
+}
+
+/// GPF: a GRF whose entries are copied into the target GRF with their
+/// compressed bytes untouched. Entries flagged for removal are deleted instead.
+fn extract_gpf(
+    gpf_path: &str,
+    target_dir: &str,
+    options: &PatchOptions,
+    patching: &PatchingConfig,
+) -> Result<PatchOutcome, String> {
+    let reader = GrfReader::new(gpf_path.to_string());
+    let header = reader.read_header()
+        .map_err(|e| format!("Failed to read GPF header: {}", e))?;
+    let table = reader.read_file_table(&header)
+        .map_err(|e| format!("Failed to read GPF table: {}", e))?;
+
+    let mut file = fs::File::open(gpf_path)
+        .map_err(|e| format!("Failed to open GPF file: {}", e))?;
+    let gpf_length = file.metadata()
+        .map_err(|e| format!("Failed to read GPF size: {}", e))?
+        .len();
+
+    // Copy in file order so the patch is read sequentially
+    let mut entries: Vec<_> = table.values().collect();
+    entries.sort_by_key(|entry| entry.offset);
+
+    let mut target = PatchTarget::new(target_dir, &options.target_grf, patching, !options.force_extract)?;
+
+    for entry in entries {
+        // 0x1xx archives have no removal entries; their flags carry encryption bits
+        if entry.flags == GRF_FLAG_REMOVE && !header.is_legacy() {
+            target.remove_file(&entry.filename)?;
+        } else if !entry.is_file() {
+            continue;
+        } else if entry.is_encrypted() {
+            // The target GRF only holds plain entries, so these are recompressed
+            target.add_file(&entry.filename, reader.open_entry(entry)?)?;
+        } else {
+            // Copied as is, so a bogus size must not reach the target GRF
+            let invalid = || format!("Entry {} of the GPF has an invalid size or offset", entry.display_name());
+            let compressed_size = u64::try_from(entry.compressed_size).map_err(|_| invalid())?;
+            let real_size = u64::try_from(entry.real_size).map_err(|_| invalid())?;
+            let data_start = (GRF_HEADER_SIZE as u64).checked_add(entry.offset).ok_or_else(invalid)?;
+            if data_start.checked_add(compressed_size).is_none_or(|data_end| data_end > gpf_length) {
+                return Err(invalid());
+            }
+
+            use std::io::Seek;
+            file.seek(std::io::SeekFrom::Start(data_start))
+                .map_err(|e| format!("Failed to seek to {}: {}", entry.display_name(), e))?;
+
+            target.add_compressed(&entry.filename, &mut file, compressed_size, real_size)?;
         }
     }
 
-    apply_patch_files(target_dir, default_grf_name, grf_files, disk_files)
+    target.finish()?;
+    Ok(PatchOutcome::Applied)
+}
+
+/// Reads the only entry of a mode 0x21 THOR and writes it to disk:
+/// compressed size, real size, name length, name, then the zlib data.
+fn extract_single_file(file: &mut fs::File, target_dir: &str) -> Result<PatchOutcome, String> {
+
+    let mut entry_info = [0u8; 9];
+    file.read_exact(&mut entry_info)
+        .map_err(|e| format!("Failed to read entry header: {}", e))?;
+
+    let size_compressed = u32::from_le_bytes([entry_info[0], entry_info[1], entry_info[2], entry_info[3]]);
+    let size_decompressed = u32::from_le_bytes([entry_info[4], entry_info[5], entry_info[6], entry_info[7]]);
+
+    let mut name = vec![0u8; entry_info[8] as usize];
+    file.read_exact(&mut name)
+        .map_err(|e| format!("Failed to read entry name: {}", e))?;
+    let name = decode_name(&name);
+
+    let mut entry_compressed = vec![0u8; size_compressed as usize];
+    file.read_exact(&mut entry_compressed)
+        .map_err(|e| format!("Failed to read {}: {}", name, e))?;
+
+    let mut entry_data = Vec::with_capacity(size_decompressed as usize);
+    if flate2::read::ZlibDecoder::new(&entry_compressed[..]).read_to_end(&mut entry_data).is_err() {
+        println!("Warning: Failed to decompress {}, assuming stored", name);
+        entry_data = entry_compressed;
+    }
+
+    if entry_data.len() != size_decompressed as usize {
+        return Err(format!(
+            "Size mismatch for {}: expected {} bytes, got {}",
+            name,
+            size_decompressed,
+            entry_data.len()
+        ));
+    }
+
+    replace_executable(&safe_join(Path::new(target_dir), &name)?, &entry_data)
+}
+
+/// Replaces an executable on disk. Windows does not allow overwriting a
+/// running program but does allow renaming it, so the running launcher is
+/// moved aside to `<name>.old` and removed on the next start.
+fn replace_executable(target_path: &Path, data: &[u8]) -> Result<PatchOutcome, String> {
+    let is_running_exe = match (std::env::current_exe(), fs::canonicalize(target_path)) {
+        (Ok(current), Ok(target)) => fs::canonicalize(current).map(|current| current == target).unwrap_or(false),
+        _ => false,
+    };
+
+    if let Some(parent) = target_path.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|e| format!("Failed to create directory: {}", e))?;
+    }
+
+    // Write next to the target first so a failed write never leaves a truncated exe behind
+    let new_path = append_to_path(target_path, ".new");
+    fs::write(&new_path, data)
+        .map_err(|e| format!("Failed to write {}: {}", new_path.display(), e))?;
+
+    let old_path = append_to_path(target_path, REPLACED_EXE_SUFFIX);
+    if is_running_exe {
+        let _ = fs::remove_file(&old_path);
+        if let Err(e) = fs::rename(target_path, &old_path) {
+            let _ = fs::remove_file(&new_path);
+            return Err(format!("Failed to move running executable aside: {}", e));
+        }
+    }
+
+    if let Err(e) = fs::rename(&new_path, target_path) {
+        let _ = fs::remove_file(&new_path);
+        if is_running_exe {
+            let _ = fs::rename(&old_path, target_path);
+        }
+        return Err(format!("Failed to replace {}: {}", target_path.display(), e));
+    }
+
+    Ok(if is_running_exe { PatchOutcome::RestartRequired } else { PatchOutcome::Applied })
+}
+
+fn append_to_path(path: &Path, suffix: &str) -> std::path::PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    name.into()
+}
+
+/// Deletes the executable left behind by a launcher self-update.
+pub fn remove_replaced_executable() {
+    if let Ok(current) = std::env::current_exe() {
+        let old_path = append_to_path(&current, REPLACED_EXE_SUFFIX);
+        if old_path.exists() {
+            if let Err(e) = fs::remove_file(&old_path) {
+                eprintln!("Failed to remove {}: {}", old_path.display(), e);
+            }
+        }
+    }
+}
+
+/// Uses forward slashes in a raw (CP949) entry name. Trail bytes of
+/// double-byte characters never collide with `\\`.
+fn normalize_name(name: &[u8]) -> Vec<u8> {
+    name.iter()
+        .map(|&byte| if byte == b'\\' { b'/' } else { byte })
+        .collect()
 }
 
 pub fn _validate_thor_patch(thor_path: &str) -> Result<bool, String> {
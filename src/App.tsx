@@ -2,7 +2,7 @@ import React, { useState, useEffect, useCallback } from 'react';
 import { PatcherUI } from './components/PatcherUI';
 import { SettingsModal } from './components/SettingsModal';
 import { TitleBar } from './components/TitleBar';
-import { PatcherConfig, PatchingStatus, DownloadProgress } from './types/patcher';
+import { PatcherConfig, PatchingStatus, DownloadProgress, RepackProgress, MirrorRanking } from './types/patcher';
 import { invoke } from '@tauri-apps/api/tauri';
 import { listen } from '@tauri-apps/api/event';
 
@@ -15,6 +15,7 @@ const App: React.FC = () => {
     const [status, setStatus] = useState<PatchingStatus>({ status: 'idle' });
     const [progress, setProgress] = useState<DownloadProgress | null>(null);
     const [error, setError] = useState<string | null>(null);
+    const [mirrors, setMirrors] = useState<MirrorRanking[]>([]);
     const [isMuted, setIsMuted] = useState(false);
     const [isGrayFloor, setIsGrayFloor] = useState(false);
     const [audio] = useState(new Audio());
@@ -123,6 +124,8 @@ const App: React.FC = () => {
 
         let unlistenStatus: (() => void) | null = null;
         let unlistenProgress: (() => void) | null = null;
+        let unlistenOptimize: (() => void) | null = null;
+        let unlistenMirrors: (() => void) | null = null;
 
         const setupListeners = async () => {
             unlistenStatus = await listen<PatchingStatus>('patching-status', (event) => {
@@ -135,6 +138,23 @@ const App: React.FC = () => {
             unlistenProgress = await listen<DownloadProgress>('download-progress', (event) => {
                 setProgress(event.payload);
             });
+
+            unlistenOptimize = await listen<RepackProgress>('optimize-progress', (event) => {
+                setProgress({
+                    filename: event.payload.grf,
+                    downloaded: event.payload.current,
+                    total: event.payload.total,
+                    speed: 0,
+                    percentage: event.payload.percentage
+                });
+            });
+
+            unlistenMirrors = await listen<MirrorRanking[]>('mirror-ranking', (event) => {
+                setMirrors(event.payload);
+            });
+
+            // A probe may have finished before the listener was registered
+            setMirrors(await invoke<MirrorRanking[]>('get_mirror_ranking'));
         };
 
         setupListeners();
@@ -142,6 +162,8 @@ const App: React.FC = () => {
         return () => {
             if (unlistenStatus) unlistenStatus();
             if (unlistenProgress) unlistenProgress();
+            if (unlistenOptimize) unlistenOptimize();
+            if (unlistenMirrors) unlistenMirrors();
         };
     }, []);
 
@@ -264,6 +286,21 @@ const App: React.FC = () => {
         }
     }, [handleStartUpdate]);
 
+    const handleOptimize = useCallback(async () => {
+        if (!isTauri) return;
+
+        setError(null);
+        setProgress(null);
+        try {
+            const result = await invoke<{ success: boolean; error?: string }>('optimize_grf');
+            if (!result.success && result.error) {
+                setError(result.error);
+            }
+        } catch (error: any) {
+            setError(error);
+        }
+    }, []);
+
     const handleToggleGrf = useCallback(async () => {
         if (!isTauri) {
             setIsGrayFloor(!isGrayFloor);
@@ -349,6 +386,7 @@ const App: React.FC = () => {
                     onSetup={config?.setup ? handleSetup : undefined}
                     onLogin={handleLogin}
                     onResetCache={handleResetCache}
+                    onOptimize={handleOptimize}
                     onRetry={handleStartUpdate}
                     onCancel={handleCancelUpdate}
                     isGrayFloor={isGrayFloor}
@@ -357,6 +395,7 @@ const App: React.FC = () => {
                     config={config}
                     onLaunchExe={handleLaunchExe}
                     onOpenExternal={handleOpenExternal}
+                    mirrors={mirrors}
                 />
             </main>
 
@@ -1,5 +1,5 @@
 import React, { useState } from 'react';
-import { PatchingStatus, DownloadProgress, CustomAction } from '../types/patcher';
+import { PatchingStatus, DownloadProgress, CustomAction, MirrorRanking } from '../types/patcher';
 import { ProgressBar } from './ProgressBar';
 
 interface PatcherUIProps {
@@ -11,6 +11,7 @@ interface PatcherUIProps {
     onSetup?: () => void;
     onLogin: (username: string, password: string) => void;
     onResetCache: () => void;
+    onOptimize: () => void;
     onRetry: () => void;
     onCancel: () => void;
     isGrayFloor: boolean;
@@ -19,6 +20,7 @@ interface PatcherUIProps {
     config: any; // Using any to avoid complex type drilling, or import PatcherConfig
     onLaunchExe?: (path: string) => void;
     onOpenExternal?: (url: string) => void;
+    mirrors?: MirrorRanking[];
 }
 
 export const PatcherUI: React.FC<PatcherUIProps> = ({
@@ -30,6 +32,7 @@ export const PatcherUI: React.FC<PatcherUIProps> = ({
     onSetup,
     onLogin,
     onResetCache,
+    onOptimize,
     onRetry,
     onCancel,
     isGrayFloor,
@@ -37,12 +40,13 @@ export const PatcherUI: React.FC<PatcherUIProps> = ({
     isSSOEnabled,
     config,
     onLaunchExe,
-    onOpenExternal
+    onOpenExternal,
+    mirrors = []
 }) => {
     const [username, setUsername] = useState('');
     const [password, setPassword] = useState('');
 
-    const isPatching = ['checking', 'downloading', 'patching'].includes(status.status);
+    const isPatching = ['checking', 'downloading', 'patching', 'optimizing'].includes(status.status);
 
     const handleLogin = (e: React.FormEvent) => {
         e.preventDefault();
@@ -60,6 +64,7 @@ export const PatcherUI: React.FC<PatcherUIProps> = ({
                 case 'checking': return 'Checking for updates...';
                 case 'downloading': return `Downloading: ${status.filename} (${status.current}/${status.total})`;
                 case 'patching': return `Applying: ${status.filename} (${status.current}/${status.total})`;
+                case 'optimizing': return `Optimizing: ${status.filename} (${status.current}/${status.total})`;
                 case 'ready': return 'Game is up to date!';
                 case 'error': return 'An error occurred';
                 default: return '';
@@ -81,6 +86,11 @@ export const PatcherUI: React.FC<PatcherUIProps> = ({
                     .replace('${filename}', status.filename || '')
                     .replace('${current}', String(status.current || 0))
                     .replace('${total}', String(status.total || 0));
+            case 'optimizing':
+                return (msgs.optimizing || 'Optimizing: ${filename} (${current}/${total})')
+                    .replace('${filename}', status.filename || '')
+                    .replace('${current}', String(status.current || 0))
+                    .replace('${total}', String(status.total || 0));
             case 'ready':
                 return msgs.ready || 'Game is up to date!';
             case 'error':
@@ -191,6 +201,18 @@ export const PatcherUI: React.FC<PatcherUIProps> = ({
                             <ServerStatus name="Char" status="online" />
                             <ServerStatus name="Map" status="online" />
                         </div>
+                        {mirrors.length > 0 && (
+                            <div className="space-y-1 mt-3 pt-3 border-t border-slate-700">
+                                {mirrors.map((mirror) => (
+                                    <div key={mirror.name} className="flex items-center justify-between" title={mirror.error ?? undefined}>
+                                        <span className="text-xs text-slate-400 truncate">{mirror.name}</span>
+                                        <span className={`text-xs ${mirror.latency_ms != null ? 'text-slate-300' : 'text-red-400'}`}>
+                                            {mirror.latency_ms != null ? `${mirror.latency_ms} ms` : 'offline'}
+                                        </span>
+                                    </div>
+                                ))}
+                            </div>
+                        )}
                     </div>
 
                     {/* Quick Actions */}
@@ -225,6 +247,13 @@ export const PatcherUI: React.FC<PatcherUIProps> = ({
                             >
                                 {config?.messages?.ui?.buttons?.reset_cache || 'Reset Cache'}
                             </button>
+                            <button
+                                onClick={onOptimize}
+                                disabled={isPatching}
+                                className="w-full btn-secondary text-xs disabled:opacity-50"
+                            >
+                                {config?.messages?.ui?.buttons?.optimize || 'Optimize client files'}
+                            </button>
 
                             {/* Config-Driven Custom Actions */}
                             {config?.custom_actions?.map((action: CustomAction, index: number) => (
@@ -257,7 +286,7 @@ export const PatcherUI: React.FC<PatcherUIProps> = ({
                         <span className={`font-medium ${status.status === 'error' ? 'text-red-400' : 'text-slate-400'}`}>
                             {getStatusText()}
                         </span>
-                        {progress && (
+                        {progress && progress.speed > 0 && (
                             <span className="text-blue-400 font-mono">
                                 {formatSpeed(progress.speed)}
                             </span>
@@ -70,6 +70,10 @@ export interface PatcherConfig {
         in_place: boolean;
         check_integrity: boolean;
         create_grf: boolean;
+        new_grf_version?: number;
+        register_new_grf?: boolean;
+        register_new_grf_first?: boolean;
+        concurrent_downloads?: number;
     };
     messages?: {
         patching?: {
@@ -94,6 +98,7 @@ export interface PatcherConfig {
                 toggle_normal?: string;
                 manual_patch?: string;
                 reset_cache?: string;
+                optimize?: string;
                 cancel?: string;
                 play?: string;
                 patching?: string;
@@ -105,6 +110,7 @@ export interface PatcherConfig {
                 checking?: string;
                 downloading?: string;
                 patching?: string;
+                optimizing?: string;
                 ready?: string;
                 error?: string;
             };
@@ -120,7 +126,7 @@ export interface CustomAction {
     color?: string; // Optional hex or class name (will be sanitized/handled in UI)
 }
 
-export type PatchingStatusType = 'idle' | 'checking' | 'downloading' | 'patching' | 'ready' | 'error';
+export type PatchingStatusType = 'idle' | 'checking' | 'downloading' | 'patching' | 'optimizing' | 'ready' | 'error';
 
 export interface PatchingStatus {
     status: PatchingStatusType;
@@ -138,6 +144,20 @@ export interface DownloadProgress {
     percentage: number;
 }
 
+// Patch mirrors ordered by probe latency, fastest (or last used) first
+export interface MirrorRanking {
+    name: string;
+    latency_ms?: number | null;
+    error?: string | null;
+}
+
+export interface RepackProgress {
+    grf: string;
+    current: number;
+    total: number;
+    percentage: number;
+}
+
 // Declare global window type extension
 declare global {
     interface Window {